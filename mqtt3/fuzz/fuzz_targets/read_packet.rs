@@ -0,0 +1,15 @@
+//! `read_packet` must return an error on any input instead of panicking.
+//!
+//! A remaining length claims up to 256MB, so run with a memory limit to catch
+//! allocations sized from the header instead of from received bytes:
+//!
+//!     cargo fuzz run read_packet -- -malloc_limit_mb=16
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use mqtt3::MqttRead;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let mut stream = Cursor::new(data.to_vec());
+    while stream.read_packet().is_ok() {}
+});
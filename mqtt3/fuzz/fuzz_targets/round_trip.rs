@@ -0,0 +1,16 @@
+//! Every packet `read_packet` accepts is written back and read again unchanged.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use mqtt3::{MqttRead, MqttWrite};
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let packet = match Cursor::new(data.to_vec()).read_packet() {
+        Ok(packet) => packet,
+        Err(_) => return
+    };
+    let mut stream = Cursor::new(Vec::new());
+    stream.write_packet(&packet).unwrap();
+    stream.set_position(0);
+    assert_eq!(stream.read_packet().unwrap(), packet);
+});
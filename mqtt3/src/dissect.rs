@@ -0,0 +1,490 @@
+use std::fmt;
+use {Error, Result, PacketType, Protocol, QoS, ConnectReturnCode};
+
+const RAW_BYTES_SHOWN: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadFormat {
+    Utf8,
+    Hex,
+    Json
+}
+
+/// One field of a packet and where it is located in the raw bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub offset: usize,
+    pub len: usize,
+    pub name: String,
+    pub value: String,
+    // flag bits are nested under the byte they belong to
+    pub depth: usize
+}
+
+/// The place where a malformed packet went wrong.
+#[derive(Debug)]
+pub struct Malformed {
+    pub offset: usize,
+    pub error: Error
+}
+
+/// Annotated breakdown of a single packet.
+#[derive(Debug)]
+pub struct Dissection {
+    bytes: Vec<u8>,
+    pub annotations: Vec<Annotation>,
+    pub error: Option<Malformed>
+}
+
+impl Dissection {
+    /// Number of bytes taken by the packet, or by the valid part of it
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn is_malformed(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+impl fmt::Display for Dissection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for annotation in &self.annotations {
+            let raw = if annotation.depth > 0 {
+                String::new()
+            } else {
+                let bytes = &self.bytes[annotation.offset..annotation.offset + annotation.len];
+                let mut raw = to_hex(&bytes[..bytes.len().min(RAW_BYTES_SHOWN)]);
+                if bytes.len() > RAW_BYTES_SHOWN {
+                    raw.push_str(" ..");
+                }
+                raw
+            };
+            let name = format!("{:indent$}{}", "", annotation.name, indent = annotation.depth * 2);
+            let mut lines = annotation.value.lines();
+            let first = format!("{:04x}  {:<20} {:<26} {}", annotation.offset, raw, name, lines.next().unwrap_or(""));
+            writeln!(f, "{}", first.trim_end())?;
+            for line in lines {
+                writeln!(f, "{:54}{}", "", line)?;
+            }
+        }
+        if let Some(ref malformed) = self.error {
+            writeln!(f, "{:04x}  ^^ malformed: {:?}", malformed.offset, malformed.error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Breaks the first packet in `bytes` down into annotated fields.
+///
+/// Parsing stops at the first malformed field, which is reported in
+/// `Dissection::error` together with its byte offset.
+pub fn dissect(bytes: &[u8], format: PayloadFormat) -> Dissection {
+    let mut dissector = Dissector {
+        bytes: bytes,
+        pos: 0,
+        mark: 0,
+        end: bytes.len(),
+        format: format,
+        annotations: Vec::new()
+    };
+    let error = match dissector.packet() {
+        Ok(()) => None,
+        Err(err) => Some(Malformed { offset: dissector.mark, error: err })
+    };
+    Dissection {
+        bytes: bytes[..dissector.pos].to_vec(),
+        annotations: dissector.annotations,
+        error: error
+    }
+}
+
+struct Dissector<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    // start of the field being read
+    mark: usize,
+    // end of the packet, once the remaining length is known
+    end: usize,
+    format: PayloadFormat,
+    annotations: Vec<Annotation>
+}
+
+impl<'a> Dissector<'a> {
+    fn packet(&mut self) -> Result<()> {
+        let hd = self.take(1)?[0];
+        let typ = PacketType::from_hd(hd);
+        let name = match typ {
+            Ok(typ) => format!("{} ({})", typ.to_string().to_uppercase(), hd >> 4),
+            Err(_) => format!("unknown ({})", hd >> 4)
+        };
+        self.annotate("packet type", name);
+        let typ = typ?;
+        match typ {
+            PacketType::Publish => {
+                self.flag("dup", hd & 0b1000 != 0);
+                self.annotate_bit(format!("qos: {}", (hd & 0b110) >> 1));
+                self.flag("retain", hd & 1 != 0);
+            },
+            _ => self.annotate_bit(format!("flags: {:04b}", hd & 0x0F))
+        }
+        let qos = QoS::from_hd(hd);
+
+        let len = self.remaining_length()?;
+        self.end = self.pos + len;
+
+        match typ {
+            PacketType::Connect => self.connect()?,
+            PacketType::Connack => {
+                let flags = self.u8("acknowledge flags")?;
+                self.amend(format!("{:08b}", flags));
+                self.flag("session present", flags & 1 != 0);
+                let code = self.u8("return code")?;
+                let code = ConnectReturnCode::from_u8(code)?;
+                self.amend(format!("{} {:?}", code.to_u8(), code));
+            },
+            PacketType::Publish => {
+                self.string("topic name")?;
+                if qos? != QoS::AtMostOnce {
+                    self.u16("packet identifier")?;
+                }
+                self.payload()?;
+            },
+            PacketType::Puback | PacketType::Pubrec | PacketType::Pubrel |
+            PacketType::Pubcomp | PacketType::Unsuback => {
+                self.u16("packet identifier")?;
+            },
+            PacketType::Subscribe => {
+                self.u16("packet identifier")?;
+                while self.pos < self.end {
+                    self.string("topic filter")?;
+                    let qos = self.u8("requested qos")?;
+                    QoS::from_u8(qos)?;
+                }
+            },
+            PacketType::Suback => {
+                self.u16("packet identifier")?;
+                while self.pos < self.end {
+                    let code = self.u8("return code")?;
+                    if code == 0x80 {
+                        self.amend("128 Failure".to_string());
+                    } else {
+                        QoS::from_u8(code)?;
+                    }
+                }
+            },
+            PacketType::Unsubscribe => {
+                self.u16("packet identifier")?;
+                while self.pos < self.end {
+                    self.string("topic filter")?;
+                }
+            },
+            PacketType::Pingreq | PacketType::Pingresp | PacketType::Disconnect => ()
+        }
+
+        if self.pos < self.end {
+            self.mark = self.pos;
+            return Err(Error::PayloadSizeIncorrect);
+        }
+        Ok(())
+    }
+
+    fn connect(&mut self) -> Result<()> {
+        let name = self.string("protocol name")?;
+        let name_mark = self.mark;
+        let level = self.u8("protocol level")?;
+        if let Err(err) = Protocol::new(&name, level) {
+            if let Error::UnsupportedProtocolName = err {
+                self.mark = name_mark;
+            }
+            return Err(err);
+        }
+
+        let flags = self.u8("connect flags")?;
+        self.amend(format!("{:08b}", flags));
+        self.flag("username", flags & 0b10000000 != 0);
+        self.flag("password", flags & 0b01000000 != 0);
+        self.flag("will retain", flags & 0b00100000 != 0);
+        self.annotate_bit(format!("will qos: {}", (flags & 0b00011000) >> 3));
+        self.flag("will flag", flags & 0b00000100 != 0);
+        self.flag("clean session", flags & 0b00000010 != 0);
+        self.flag("reserved", flags & 0b00000001 != 0);
+        if flags & 0b100 == 0 && flags & 0b00111000 != 0 {
+            return Err(Error::IncorrectPacketFormat);
+        }
+        if flags & 0b100 != 0 {
+            QoS::from_u8((flags & 0b11000) >> 3)?;
+        }
+
+        let keep_alive = self.u16("keep alive")?;
+        self.amend(format!("{} sec", keep_alive));
+        self.string("client identifier")?;
+        if flags & 0b100 != 0 {
+            self.string("will topic")?;
+            self.string("will message")?;
+        }
+        if flags & 0b10000000 != 0 {
+            self.string("username")?;
+        }
+        if flags & 0b01000000 != 0 {
+            self.string("password")?;
+        }
+        Ok(())
+    }
+
+    fn remaining_length(&mut self) -> Result<usize> {
+        self.mark = self.pos;
+        let start = self.pos;
+        let mut mult: usize = 1;
+        let mut len: usize = 0;
+        loop {
+            let byte = match self.bytes.get(self.pos) {
+                Some(byte) => *byte as usize,
+                None => return Err(Error::UnexpectedEof)
+            };
+            self.pos += 1;
+            len += (byte & 0x7F) * mult;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            mult *= 0x80;
+            if self.pos - start == 4 {
+                self.mark = start;
+                return Err(Error::MalformedRemainingLength);
+            }
+        }
+        self.push(start, "remaining length", len.to_string(), 0);
+        Ok(len)
+    }
+
+    fn u8(&mut self, name: &str) -> Result<u8> {
+        let byte = self.take(1)?[0];
+        self.annotate(name, byte.to_string());
+        Ok(byte)
+    }
+
+    fn u16(&mut self, name: &str) -> Result<u16> {
+        let value = {
+            let bytes = self.take(2)?;
+            ((bytes[0] as u16) << 8) | bytes[1] as u16
+        };
+        self.annotate(name, value.to_string());
+        Ok(value)
+    }
+
+    fn string(&mut self, name: &str) -> Result<String> {
+        let len = {
+            let bytes = self.take(2)?;
+            ((bytes[0] as usize) << 8) | bytes[1] as usize
+        };
+        self.annotate(&format!("{} length", name), len.to_string());
+        let string = String::from_utf8(self.take(len)?.to_vec())?;
+        self.annotate(name, format!("{:?}", string));
+        Ok(string)
+    }
+
+    fn payload(&mut self) -> Result<()> {
+        let payload = self.take(self.end - self.pos)?;
+        let mut name = format!("payload ({} bytes)", payload.len());
+        let preview = match self.format {
+            PayloadFormat::Utf8 => preview_utf8(payload),
+            PayloadFormat::Hex => preview_hex(payload),
+            PayloadFormat::Json => match ::std::str::from_utf8(payload).ok().and_then(preview_json) {
+                Some(json) => {
+                    name = format!("payload ({} bytes, JSON)", payload.len());
+                    json
+                },
+                None => preview_utf8(payload)
+            }
+        };
+        self.annotate(&name, preview);
+        Ok(())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        self.mark = self.pos;
+        // the field overruns its packet, or the packet is cut short
+        if self.pos + len > self.end {
+            return Err(Error::PayloadSizeIncorrect);
+        }
+        if self.pos + len > self.bytes.len() {
+            return Err(Error::UnexpectedEof);
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn annotate(&mut self, name: &str, value: String) {
+        let offset = self.mark;
+        self.push(offset, name, value, 0);
+    }
+
+    // replaces the value of the last annotated field
+    fn amend(&mut self, value: String) {
+        if let Some(annotation) = self.annotations.last_mut() {
+            annotation.value = value;
+        }
+    }
+
+    fn annotate_bit(&mut self, value: String) {
+        let offset = self.mark;
+        self.push(offset, &value, String::new(), 1);
+    }
+
+    fn flag(&mut self, name: &str, value: bool) {
+        self.annotate_bit(format!("{}: {}", name, value as u8));
+    }
+
+    fn push(&mut self, offset: usize, name: &str, value: String, depth: usize) {
+        self.annotations.push(Annotation {
+            offset: offset,
+            len: if depth > 0 { 0 } else { self.pos - offset },
+            name: name.to_string(),
+            value: value,
+            depth: depth
+        });
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ")
+}
+
+fn preview_utf8(payload: &[u8]) -> String {
+    match ::std::str::from_utf8(payload) {
+        Ok(text) => format!("{:?}", text),
+        Err(_) => preview_hex(payload)
+    }
+}
+
+fn preview_hex(payload: &[u8]) -> String {
+    payload.chunks(16).map(|line| {
+        let ascii: String = line.iter().map(|&b| if b >= 0x20 && b < 0x7f { b as char } else { '.' }).collect();
+        format!("{:<47}  {}", to_hex(line), ascii)
+    }).collect::<Vec<_>>().join("\n")
+}
+
+/// Pretty-prints a JSON object or array, or gives `None` when it doesn't parse.
+#[cfg(feature = "serde")]
+fn preview_json(text: &str) -> Option<String> {
+    let text = text.trim();
+    if !(text.starts_with('{') || text.starts_with('[')) {
+        return None;
+    }
+    let value: ::serde_json::Value = ::serde_json::from_str(text).ok()?;
+    ::serde_json::to_string_pretty(&value).ok()
+}
+
+/// JSON payloads are shown as text without the `serde` feature
+#[cfg(not(feature = "serde"))]
+fn preview_json(_: &str) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dissect, PayloadFormat};
+    use Error;
+
+    #[test]
+    fn dissect_publish_test() {
+        let bytes = vec![
+            0b00110010, 9,
+            0x00, 0x03, 'a' as u8, '/' as u8, 'b' as u8, // topic name = 'a/b'
+            0x00, 0x0a, // pid = 10
+            'h' as u8, 'i' as u8
+        ];
+        let dissection = dissect(&bytes, PayloadFormat::Utf8);
+        assert!(!dissection.is_malformed());
+        assert_eq!(dissection.len(), 11);
+
+        let fields: Vec<(usize, &str, &str)> = dissection.annotations.iter()
+            .map(|a| (a.offset, a.name.as_str(), a.value.as_str()))
+            .collect();
+        assert_eq!(fields, vec![
+            (0, "packet type", "PUBLISH (3)"),
+            (0, "dup: 0", ""),
+            (0, "qos: 1", ""),
+            (0, "retain: 0", ""),
+            (1, "remaining length", "9"),
+            (2, "topic name length", "3"),
+            (4, "topic name", "\"a/b\""),
+            (7, "packet identifier", "10"),
+            (9, "payload (2 bytes)", "\"hi\"")
+        ]);
+    }
+
+    #[test]
+    fn dissect_connect_test() {
+        let bytes = vec![
+            0x10, 18,
+            0x00, 0x06, 'M' as u8, 'Q' as u8, 'I' as u8, 's' as u8, 'd' as u8, 'p' as u8,
+            0x03,
+            0b00000010,
+            0x00, 0x3c,
+            0x00, 0x04, 't' as u8, 'e' as u8, 's' as u8, 't' as u8
+        ];
+        let dissection = dissect(&bytes, PayloadFormat::Utf8);
+        assert!(!dissection.is_malformed());
+        let output = dissection.to_string();
+        assert!(output.contains("\"MQIsdp\""));
+        assert!(output.contains("clean session: 1"));
+        assert!(output.contains("60 sec"));
+    }
+
+    #[test]
+    fn dissect_malformed_test() {
+        // topic name claims 5 bytes, only 3 left in the packet
+        let bytes = vec![0x30, 5, 0x00, 0x05, 'a' as u8, '/' as u8, 'b' as u8];
+        let dissection = dissect(&bytes, PayloadFormat::Utf8);
+        let malformed = dissection.error.unwrap();
+        assert_eq!(malformed.offset, 4);
+        match malformed.error {
+            Error::PayloadSizeIncorrect => (),
+            err => panic!("unexpected {:?}", err)
+        }
+
+        // truncated stream
+        let dissection = dissect(&[0x82, 10, 0x00], PayloadFormat::Utf8);
+        assert_eq!(dissection.error.unwrap().offset, 2);
+
+        // unsupported protocol level
+        let bytes = vec![0x10, 10, 0x00, 0x04, 'M' as u8, 'Q' as u8, 'T' as u8, 'T' as u8, 0x05, 0x00, 0x00, 0x00];
+        let dissection = dissect(&bytes, PayloadFormat::Utf8);
+        assert_eq!(dissection.error.unwrap().offset, 8);
+    }
+
+    #[test]
+    fn dissect_payload_format_test() {
+        let bytes = vec![0x30, 12, 0x00, 0x01, 'a' as u8, '{' as u8, '"' as u8, 'k' as u8, '"' as u8, ':' as u8, '[' as u8, '1' as u8, ']' as u8, '}' as u8];
+        if cfg!(feature = "serde") {
+            let json = dissect(&bytes, PayloadFormat::Json);
+            assert_eq!(json.annotations.last().unwrap().value, "{\n  \"k\": [\n    1\n  ]\n}");
+        }
+        let hex = dissect(&bytes, PayloadFormat::Hex);
+        assert!(hex.annotations.last().unwrap().value.starts_with("7b 22 6b 22 3a 5b 31 5d 7d"));
+    }
+
+    #[test]
+    fn dissect_payload_json_test() {
+        let publish = |payload: &str| {
+            let mut bytes = vec![0x30, 3 + payload.len() as u8, 0x00, 0x01, 'a' as u8];
+            bytes.extend(payload.bytes());
+            dissect(&bytes, PayloadFormat::Json).annotations.pop().unwrap()
+        };
+        assert_eq!(publish("{abc").value, "\"{abc\"");
+        assert_eq!(publish("[1,,]").value, "\"[1,,]\"");
+        if cfg!(feature = "serde") {
+            assert!(publish("[1]").name.ends_with("JSON)"));
+            // keys keep their order
+            assert_eq!(publish("{\"b\":1,\"a\":2}").value, "{\n  \"b\": 1,\n  \"a\": 2\n}");
+        } else {
+            assert_eq!(publish("[1]").name, "payload (3 bytes)");
+        }
+    }
+}
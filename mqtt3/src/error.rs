@@ -21,6 +21,12 @@ pub enum Error {
     PayloadRequired,
     TopicNameMustNotContainNonUtf8,
     TopicNameMustNotContainWildcard,
+    TopicFilterWildcardMustOccupyLevel,
+    TopicFilterMultiWildcardMustBeLast,
+    TopicFilterInvalidShare,
+    TopicPathMustNotBeEmpty,
+    TopicPathMustNotContainNul,
+    TopicPathTooLong,
     MalformedRemainingLength,
     UnexpectedEof,
     Io(io::Error)
@@ -68,6 +74,12 @@ impl error::Error for Error {
             Error::PayloadRequired => "Payload Required",
             Error::TopicNameMustNotContainNonUtf8 => "Topic Name Must Not Contain Non Utf 8",
             Error::TopicNameMustNotContainWildcard => "Topic Name Must Not Contain Wildcard",
+            Error::TopicFilterWildcardMustOccupyLevel => "Topic Filter Wildcard Must Occupy Level",
+            Error::TopicFilterMultiWildcardMustBeLast => "Topic Filter Multi Wildcard Must Be Last",
+            Error::TopicFilterInvalidShare => "Topic Filter Invalid Share",
+            Error::TopicPathMustNotBeEmpty => "Topic Path Must Not Be Empty",
+            Error::TopicPathMustNotContainNul => "Topic Path Must Not Contain Nul",
+            Error::TopicPathTooLong => "Topic Path Too Long",
             Error::MalformedRemainingLength => "Malformed Remaining Length",
             Error::UnexpectedEof => "Unexpected Eof",
             Error::Io(ref err) => err.description(),
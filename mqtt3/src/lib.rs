@@ -1,4 +1,14 @@
 extern crate byteorder;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "base64")]
+extern crate base64;
+#[cfg(any(test, feature = "proptest"))]
+extern crate proptest;
+#[cfg(feature = "serde")]
+#[cfg_attr(test, macro_use)]
+extern crate serde_json;
 
 mod error;
 mod mqtt;
@@ -6,6 +16,11 @@ mod read;
 mod write;
 mod topic;
 mod msg;
+mod dissect;
+#[cfg(feature = "serde")]
+pub mod payload;
+#[cfg(any(test, feature = "proptest"))]
+pub mod strategy;
 
 pub use error::{
     Error,
@@ -34,6 +49,14 @@ pub use topic::{
     ToTopicPath
 };
 
+pub use dissect::{
+    dissect,
+    Dissection,
+    Annotation,
+    Malformed,
+    PayloadFormat
+};
+
 pub use read::MqttRead;
 pub use write::MqttWrite;
 
@@ -43,6 +66,7 @@ const MAX_PAYLOAD_SIZE: usize = 268435455;
 use std::fmt;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Protocol {
     MQIsdp(u8),
     MQTT(u8)
@@ -79,6 +103,7 @@ impl Protocol {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum QoS {
     AtMostOnce,
     AtLeastOnce,
@@ -124,6 +149,7 @@ impl QoS {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum PacketType {
 	Connect,
 	Connack,
@@ -197,6 +223,7 @@ impl fmt::Display for PacketType {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ConnectReturnCode {
     Accepted,
     RefusedProtocolVersion,
@@ -232,6 +259,7 @@ impl ConnectReturnCode {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PacketIdentifier(pub u16);
 
 impl PacketIdentifier {
@@ -245,6 +273,7 @@ impl PacketIdentifier {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Header {
     hd: u8,
     pub typ: PacketType,
@@ -276,7 +305,34 @@ impl Header {
     }
 }
 
+/// Goes through `Header::new`, so `hd` names a packet type and, for
+/// PUBLISH, a valid QoS
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for Header {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Header, D::Error> {
+        use serde::de::Error;
+
+        #[derive(Deserialize)]
+        struct Raw {
+            hd: u8,
+            typ: Option<PacketType>,
+            len: usize
+        }
+
+        let raw = Raw::deserialize(deserializer)?;
+        let header = Header::new(raw.hd, raw.len).map_err(D::Error::custom)?;
+        if raw.typ.is_some_and(|typ| typ != header.typ) {
+            return Err(D::Error::custom("typ doesn't match hd"));
+        }
+        if header.typ == PacketType::Publish {
+            header.qos().map_err(D::Error::custom)?;
+        }
+        Ok(header)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct LastWill {
     pub topic: String,
     pub message: String,
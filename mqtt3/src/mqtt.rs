@@ -2,6 +2,7 @@ use std::sync::Arc;
 use super::{QoS, LastWill, PacketIdentifier, Protocol, ConnectReturnCode};
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Packet {
 	Connect(Box<Connect>),
 	Connack(Connack),
@@ -20,6 +21,7 @@ pub enum Packet {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Connect {
 	pub protocol: Protocol,
     pub keep_alive: u16,
@@ -31,22 +33,26 @@ pub struct Connect {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Connack {
     pub session_present: bool,
     pub code: ConnectReturnCode
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Publish {
     pub dup: bool,
     pub qos: QoS,
     pub retain: bool,
     pub topic_name: String,
     pub pid: Option<PacketIdentifier>,
+    #[cfg_attr(feature = "serde", serde(with = "::payload"))]
     pub payload: Arc<Vec<u8>>
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Subscribe {
     pub pid: PacketIdentifier,
 	// (topic path, qos)
@@ -54,12 +60,14 @@ pub struct Subscribe {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SubscribeTopic {
 	pub topic_path: String,
 	pub qos: QoS
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Suback {
     pub pid: PacketIdentifier,
 	// (error, qos)
@@ -68,12 +76,14 @@ pub struct Suback {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum SubscribeReturnCodes {
 	Success(QoS),
 	Failure
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Unsubscribe {
     pub pid: PacketIdentifier,
 	pub topics: Vec<String>
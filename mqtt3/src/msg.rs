@@ -1,23 +1,22 @@
 use std::sync::Arc;
 use std::vec::Vec;
-use {Publish, TopicPath, PacketIdentifier, QoS, LastWill, Error, Result};
+use {Publish, TopicPath, PacketIdentifier, QoS, LastWill, Result};
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Message {
     pub topic: TopicPath,
     pub qos: QoS,
     pub retain: bool,
     // Only for QoS 1,2
     pub pid: Option<PacketIdentifier>,
+    #[cfg_attr(feature = "serde", serde(with = "::payload"))]
     pub payload: Arc<Vec<u8>>
 }
 
 impl Message {
     pub fn from_pub(publish: Box<Publish>) -> Result<Box<Message>> {
-        let topic = TopicPath::from(publish.topic_name.as_str());
-        if topic.wildcards {
-            return Err(Error::TopicNameMustNotContainWildcard);
-        }
+        let topic = try!(TopicPath::from_name(publish.topic_name.as_str()));
         Ok(Box::new(Message {
             topic: topic,
             qos: publish.qos,
@@ -27,16 +26,16 @@ impl Message {
         }))
     }
 
-    pub fn from_last_will(last_will: LastWill) -> Box<Message> {
-        let topic = TopicPath::from(last_will.topic);
+    pub fn from_last_will(last_will: LastWill) -> Result<Box<Message>> {
+        let topic = try!(TopicPath::from_name(last_will.topic));
 
-        Box::new(Message {
+        Ok(Box::new(Message {
             topic: topic,
             qos: last_will.qos,
             retain: last_will.retain,
             pid: None,
             payload: Arc::new(last_will.message.into_bytes())
-        })
+        }))
     }
 
     pub fn to_pub(&self, qos: Option<QoS>, dup: bool) -> Box<Publish> {
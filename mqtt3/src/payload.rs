@@ -0,0 +1,117 @@
+//! Serde representations of a packet payload.
+//!
+//! `Publish` and `Message` write their payload as an array of bytes. With the
+//! `serde-base64` feature they use a base64 string instead. Either module can
+//! also be picked per field: `#[serde(with = "mqtt3::payload::bytes")]`.
+
+#[cfg(not(feature = "serde-base64"))]
+pub use self::bytes::{serialize, deserialize};
+
+#[cfg(feature = "serde-base64")]
+pub use self::base64::{serialize, deserialize};
+
+/// Payload as an array of bytes: `[104, 105]`
+pub mod bytes {
+    use std::sync::Arc;
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+    pub fn serialize<S: Serializer>(payload: &Arc<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        payload.as_slice().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<Vec<u8>>, D::Error> {
+        Vec::<u8>::deserialize(deserializer).map(Arc::new)
+    }
+}
+
+/// Payload as a standard base64 string: `"aGk="`
+#[cfg(feature = "serde-base64")]
+pub mod base64 {
+    use std::sync::Arc;
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+    use serde::de::Error;
+    use ::base64::Engine;
+    use ::base64::engine::general_purpose::STANDARD;
+
+    pub fn serialize<S: Serializer>(payload: &Arc<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        STANDARD.encode(payload.as_slice()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<Vec<u8>>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map(Arc::new).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use serde_json;
+    use {Packet, Publish, Message, QoS, PacketIdentifier, TopicPath, ToTopicPath, Header};
+
+    fn publish() -> Packet {
+        Packet::Publish(Box::new(Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            topic_name: "a/b".to_owned(),
+            pid: Some(PacketIdentifier(10)),
+            payload: Arc::new(vec![0x68, 0x69])
+        }))
+    }
+
+    #[test]
+    fn packet_round_trip_test() {
+        let json = serde_json::to_string(&publish()).unwrap();
+        let packet: Packet = serde_json::from_str(&json).unwrap();
+        assert_eq!(packet, publish());
+    }
+
+    #[cfg(not(feature = "serde-base64"))]
+    #[test]
+    fn payload_bytes_test() {
+        let json = serde_json::to_value(&publish()).unwrap();
+        assert_eq!(json["Publish"]["payload"], json!([0x68, 0x69]));
+    }
+
+    #[cfg(feature = "serde-base64")]
+    #[test]
+    fn payload_base64_test() {
+        let json = serde_json::to_value(&publish()).unwrap();
+        assert_eq!(json["Publish"]["payload"], json!("aGk="));
+    }
+
+    #[test]
+    fn message_topic_path_test() {
+        let msg = Message {
+            topic: "a/b/c".to_topic_path().unwrap(),
+            qos: QoS::ExactlyOnce,
+            retain: false,
+            pid: None,
+            payload: Arc::new(Vec::new())
+        };
+        let json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(json["topic"], json!("a/b/c"));
+
+        let msg: Message = serde_json::from_value(json).unwrap();
+        assert_eq!(msg.topic.path(), "a/b/c");
+        assert_eq!(msg.topic.len(), 3);
+    }
+
+    #[test]
+    fn invalid_topic_path_test() {
+        assert!(serde_json::from_str::<TopicPath>("\"a/b+\"").is_err());
+    }
+
+    #[test]
+    fn header_test() {
+        let header = Header::new(0x32, 10).unwrap();
+        let json = serde_json::to_string(&header).unwrap();
+        assert_eq!(serde_json::from_str::<Header>(&json).unwrap(), header);
+
+        // reserved packet type, PUBLISH with QoS 3, typ against hd
+        assert!(serde_json::from_str::<Header>("{\"hd\":0,\"len\":0}").is_err());
+        assert!(serde_json::from_str::<Header>("{\"hd\":54,\"len\":0}").is_err());
+        assert!(serde_json::from_str::<Header>("{\"hd\":50,\"typ\":\"Puback\",\"len\":0}").is_err());
+    }
+}
@@ -26,6 +26,7 @@ pub trait MqttRead: ReadBytesExt {
             return match header.typ {
                 PacketType::Pingreq => Ok(Packet::Pingreq),
                 PacketType::Pingresp => Ok(Packet::Pingresp),
+                PacketType::Disconnect => Ok(Packet::Disconnect),
                 _ => Err(Error::PayloadRequired)
             };
         }
@@ -146,7 +147,7 @@ pub trait MqttRead: ReadBytesExt {
     fn read_publish(&mut self, header: Header) -> Result<Box<Publish>> {
         let topic_name = self.read_mqtt_string();
         // Packet identifier exists where QoS > 0
-        let pid = if header.qos().unwrap() != QoS::AtMostOnce {
+        let pid = if try!(header.qos()) != QoS::AtMostOnce {
             Some(PacketIdentifier(try!(self.read_u16::<BigEndian>())))
         } else {
             None
@@ -168,13 +169,13 @@ pub trait MqttRead: ReadBytesExt {
 
     fn read_subscribe(&mut self, header: Header) -> Result<Box<Subscribe>> {
         let pid = try!(self.read_u16::<BigEndian>());
-        let mut remaining_bytes = header.len - 2;
+        let mut remaining_bytes = try!(header.len.checked_sub(2).ok_or(Error::PayloadSizeIncorrect));
         let mut topics = Vec::with_capacity(1);
 
         while remaining_bytes > 0 {
             let topic_filter = try!(self.read_mqtt_string());
             let requested_qod = try!(self.read_u8());
-            remaining_bytes -= topic_filter.len() + 3;
+            remaining_bytes = try!(remaining_bytes.checked_sub(topic_filter.len() + 3).ok_or(Error::PayloadSizeIncorrect));
             topics.push(SubscribeTopic { topic_path: topic_filter, qos: try!(QoS::from_u8(requested_qod)) });
         };
 
@@ -186,8 +187,9 @@ pub trait MqttRead: ReadBytesExt {
 
     fn read_suback(&mut self, header: Header) -> Result<Box<Suback>> {
         let pid = try!(self.read_u16::<BigEndian>());
-        let mut remaining_bytes = header.len - 2;
-        let mut return_codes = Vec::with_capacity(remaining_bytes);
+        let mut remaining_bytes = try!(header.len.checked_sub(2).ok_or(Error::PayloadSizeIncorrect));
+        // don't trust the header length before the bytes actually arrive
+        let mut return_codes = Vec::with_capacity(1);
 
         while remaining_bytes > 0 {
             let return_code = try!(self.read_u8());
@@ -207,12 +209,12 @@ pub trait MqttRead: ReadBytesExt {
 
     fn read_unsubscribe(&mut self, header: Header) -> Result<Box<Unsubscribe>> {
         let pid = try!(self.read_u16::<BigEndian>());
-        let mut remaining_bytes = header.len - 2;
+        let mut remaining_bytes = try!(header.len.checked_sub(2).ok_or(Error::PayloadSizeIncorrect));
         let mut topics = Vec::with_capacity(1);
 
         while remaining_bytes > 0 {
             let topic_filter = try!(self.read_mqtt_string());
-            remaining_bytes -= topic_filter.len() + 2;
+            remaining_bytes = try!(remaining_bytes.checked_sub(topic_filter.len() + 2).ok_or(Error::PayloadSizeIncorrect));
             topics.push(topic_filter);
         };
 
@@ -232,6 +234,9 @@ pub trait MqttRead: ReadBytesExt {
         let len = try!(self.read_u16::<BigEndian>()) as usize;
         let mut data = Vec::with_capacity(len);
         try!(self.take(len as u64).read_to_end(&mut data));
+        if data.len() != len {
+            return Err(Error::UnexpectedEof);
+        }
         Ok(try!(String::from_utf8(data)))
     }
 
@@ -455,4 +460,24 @@ mod test {
             return_codes: vec![SubscribeReturnCodes::Success(QoS::AtLeastOnce), SubscribeReturnCodes::Failure]
         })));
     }
+
+    #[test]
+    fn read_packet_disconnect_test() {
+        let mut stream = Cursor::new(vec![0xe0, 0x00]);
+        let packet = stream.read_packet().unwrap();
+
+        assert_eq!(packet, Packet::Disconnect);
+    }
+
+    #[test]
+    fn read_packet_malformed_test() {
+        // publish with QoS 3
+        assert!(Cursor::new(vec![0b00110110, 0x01, 0x00]).read_packet().is_err());
+        // subscribe shorter than its packet identifier
+        assert!(Cursor::new(vec![0x82, 0x01, 0x00]).read_packet().is_err());
+        // topic filter longer than the remaining length
+        assert!(Cursor::new(vec![0x82, 0x04, 0x00, 0x01, 0x00, 0x05]).read_packet().is_err());
+        // unsubscribe with a truncated topic filter
+        assert!(Cursor::new(vec![0xA2, 0x05, 0x00, 0x01, 0x00, 0x05, 0x61]).read_packet().is_err());
+    }
 }
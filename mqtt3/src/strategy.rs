@@ -0,0 +1,161 @@
+//! Proptest strategies generating arbitrary valid packets.
+//!
+//! Available with the `proptest` feature. Every generated `Packet` survives a
+//! `write_packet`/`read_packet` round trip unchanged.
+
+use std::sync::Arc;
+use proptest::prelude::*;
+use proptest::collection::vec;
+use {Packet, Connect, Connack, Publish, Subscribe, Suback, Unsubscribe, SubscribeTopic, SubscribeReturnCodes};
+use {Protocol, QoS, LastWill, PacketIdentifier, ConnectReturnCode};
+
+/// Any MQTT string short enough to fit its 2-byte length prefix many times over
+pub fn mqtt_string() -> impl Strategy<Value = String> {
+    ".{0,24}"
+}
+
+/// Payload sizes crossing the 1, 2 and 3 byte remaining length boundaries
+pub fn payload() -> impl Strategy<Value = Arc<Vec<u8>>> {
+    prop_oneof![
+        vec(any::<u8>(), 0..128),
+        vec(any::<u8>(), 128..20000)
+    ].prop_map(Arc::new)
+}
+
+pub fn protocol() -> impl Strategy<Value = Protocol> {
+    prop_oneof![Just(Protocol::MQIsdp(3)), Just(Protocol::MQTT(4))]
+}
+
+pub fn qos() -> impl Strategy<Value = QoS> {
+    prop_oneof![Just(QoS::AtMostOnce), Just(QoS::AtLeastOnce), Just(QoS::ExactlyOnce)]
+}
+
+pub fn pid() -> impl Strategy<Value = PacketIdentifier> {
+    any::<u16>().prop_map(PacketIdentifier)
+}
+
+pub fn connect_return_code() -> impl Strategy<Value = ConnectReturnCode> {
+    prop_oneof![
+        Just(ConnectReturnCode::Accepted),
+        Just(ConnectReturnCode::RefusedProtocolVersion),
+        Just(ConnectReturnCode::RefusedIdentifierRejected),
+        Just(ConnectReturnCode::ServerUnavailable),
+        Just(ConnectReturnCode::BadUsernamePassword),
+        Just(ConnectReturnCode::NotAuthorized)
+    ]
+}
+
+pub fn last_will() -> impl Strategy<Value = LastWill> {
+    (mqtt_string(), mqtt_string(), qos(), any::<bool>()).prop_map(|(topic, message, qos, retain)| {
+        LastWill { topic: topic, message: message, qos: qos, retain: retain }
+    })
+}
+
+pub fn connect() -> impl Strategy<Value = Connect> {
+    (
+        protocol(),
+        any::<u16>(),
+        mqtt_string(),
+        any::<bool>(),
+        proptest::option::of(last_will()),
+        proptest::option::of(mqtt_string()),
+        proptest::option::of(mqtt_string())
+    ).prop_map(|(protocol, keep_alive, client_id, clean_session, last_will, username, password)| {
+        Connect {
+            protocol: protocol,
+            keep_alive: keep_alive,
+            client_id: client_id,
+            clean_session: clean_session,
+            last_will: last_will,
+            username: username,
+            password: password
+        }
+    })
+}
+
+pub fn connack() -> impl Strategy<Value = Connack> {
+    (any::<bool>(), connect_return_code()).prop_map(|(session_present, code)| {
+        Connack { session_present: session_present, code: code }
+    })
+}
+
+/// A packet identifier is present exactly when QoS > 0
+pub fn publish() -> impl Strategy<Value = Publish> {
+    (any::<bool>(), qos(), any::<bool>(), mqtt_string(), pid(), payload())
+        .prop_map(|(dup, qos, retain, topic_name, pid, payload)| {
+            Publish {
+                dup: dup,
+                qos: qos,
+                retain: retain,
+                topic_name: topic_name,
+                pid: if qos == QoS::AtMostOnce { None } else { Some(pid) },
+                payload: payload
+            }
+        })
+}
+
+pub fn subscribe_topic() -> impl Strategy<Value = SubscribeTopic> {
+    (mqtt_string(), qos()).prop_map(|(topic_path, qos)| SubscribeTopic { topic_path: topic_path, qos: qos })
+}
+
+pub fn subscribe() -> impl Strategy<Value = Subscribe> {
+    (pid(), vec(subscribe_topic(), 1..8)).prop_map(|(pid, topics)| Subscribe { pid: pid, topics: topics })
+}
+
+pub fn subscribe_return_codes() -> impl Strategy<Value = SubscribeReturnCodes> {
+    prop_oneof![qos().prop_map(SubscribeReturnCodes::Success), Just(SubscribeReturnCodes::Failure)]
+}
+
+pub fn suback() -> impl Strategy<Value = Suback> {
+    (pid(), vec(subscribe_return_codes(), 1..8)).prop_map(|(pid, return_codes)| {
+        Suback { pid: pid, return_codes: return_codes }
+    })
+}
+
+pub fn unsubscribe() -> impl Strategy<Value = Unsubscribe> {
+    (pid(), vec(mqtt_string(), 1..8)).prop_map(|(pid, topics)| Unsubscribe { pid: pid, topics: topics })
+}
+
+pub fn packet() -> impl Strategy<Value = Packet> {
+    prop_oneof![
+        connect().prop_map(|p| Packet::Connect(Box::new(p))),
+        connack().prop_map(Packet::Connack),
+        publish().prop_map(|p| Packet::Publish(Box::new(p))),
+        pid().prop_map(Packet::Puback),
+        pid().prop_map(Packet::Pubrec),
+        pid().prop_map(Packet::Pubrel),
+        pid().prop_map(Packet::Pubcomp),
+        subscribe().prop_map(|p| Packet::Subscribe(Box::new(p))),
+        suback().prop_map(|p| Packet::Suback(Box::new(p))),
+        unsubscribe().prop_map(|p| Packet::Unsubscribe(Box::new(p))),
+        pid().prop_map(Packet::Unsuback),
+        Just(Packet::Pingreq),
+        Just(Packet::Pingresp),
+        Just(Packet::Disconnect)
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use proptest::prelude::*;
+    use proptest::collection::vec;
+    use {MqttRead, MqttWrite};
+    use super::packet;
+
+    proptest! {
+        #[test]
+        fn round_trip_test(packet in packet()) {
+            let mut stream = Cursor::new(Vec::new());
+            stream.write_packet(&packet).unwrap();
+            stream.set_position(0);
+            prop_assert_eq!(stream.read_packet().unwrap(), packet);
+            prop_assert_eq!(stream.position() as usize, stream.get_ref().len());
+        }
+
+        #[test]
+        fn read_arbitrary_bytes_test(bytes in vec(any::<u8>(), 0..64)) {
+            let _ = Cursor::new(bytes).read_packet();
+        }
+    }
+}
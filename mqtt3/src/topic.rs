@@ -1,7 +1,11 @@
 use std::vec::IntoIter;
+use std::convert::TryFrom;
 use {Error, Result};
 
 const TOPIC_PATH_DELIMITER: char = '/';
+// topics are length-prefixed with two bytes
+const MAX_TOPIC_PATH_LEN: usize = 65535;
+const SHARED_SUBSCRIPTION_PREFIX: &str = "$share/";
 
 use self::Topic::{
     Normal,
@@ -13,6 +17,7 @@ use self::Topic::{
 
 /// FIXME: replace String with &str
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Topic {
     Normal(String),
     System(String), // $SYS = Topic::System("$SYS")
@@ -82,6 +87,9 @@ pub struct TopicPath {
     pub path: String,
     // Should be false for Topic Name
     pub wildcards: bool,
+    // Share group of a `$share/{group}/{filter}` subscription
+    share: Option<String>,
+    // Levels of the filter, without the `$share/{group}/` prefix
     topics: Vec<Topic>
 }
 
@@ -90,6 +98,46 @@ impl TopicPath {
         self.path.clone()
     }
 
+    /// Share group name of a shared subscription
+    pub fn share(&self) -> Option<&str> {
+        self.share.as_ref().map(|s| s.as_str())
+    }
+
+    /// The filter messages are matched against, `$share/{group}/` aside
+    pub fn filter(&self) -> &str {
+        match self.share {
+            Some(ref group) => &self.path[SHARED_SUBSCRIPTION_PREFIX.len() + group.len() + 1..],
+            None => &self.path
+        }
+    }
+
+    /// Whether a message published to `topic_name` is delivered to this filter.
+    pub fn matches(&self, topic_name: &TopicPath) -> bool {
+        // wildcards at the first level don't match topics starting with `$`
+        if let (Some(&Topic::System(_)), Some(first)) = (topic_name.topics.first(), self.topics.first()) {
+            if *first == Topic::SingleWildcard || *first == Topic::MultiWildcard {
+                return false;
+            }
+        }
+        for (index, topic) in self.topics.iter().enumerate() {
+            match *topic {
+                // `sport/#` also matches `sport`
+                Topic::MultiWildcard => return true,
+                Topic::SingleWildcard => {
+                    if index >= topic_name.topics.len() {
+                        return false;
+                    }
+                },
+                ref level => {
+                    if topic_name.topics.get(index) != Some(level) {
+                        return false;
+                    }
+                }
+            }
+        }
+        self.topics.len() == topic_name.topics.len()
+    }
+
     pub fn get(&self, index: usize) -> Option<&Topic> {
         self.topics.get(index)
     }
@@ -114,16 +162,64 @@ impl TopicPath {
         }
     }
 
+    /// Parses a topic filter, where `+` and `#` wildcards are allowed.
+    ///
+    /// A `$share/{group}/{filter}` shared subscription keeps its group apart
+    /// and is matched by `{filter}` alone.
     pub fn from_str<T: AsRef<str>>(path: T) -> Result<TopicPath> {
-        let mut valid = true;
-        let topics: Vec<Topic> = path.as_ref().split(TOPIC_PATH_DELIMITER).map( |topic| {
-            match topic {
+        let path = path.as_ref();
+        if !path.starts_with(SHARED_SUBSCRIPTION_PREFIX) {
+            return Self::parse(path);
+        }
+        let shared = &path[SHARED_SUBSCRIPTION_PREFIX.len()..];
+        let (group, filter) = match shared.find(TOPIC_PATH_DELIMITER) {
+            Some(index) => (&shared[..index], &shared[index + 1..]),
+            None => return Err(Error::TopicFilterInvalidShare)
+        };
+        if group.is_empty() || group.contains('+') || group.contains('#') || filter.is_empty() {
+            return Err(Error::TopicFilterInvalidShare);
+        }
+        let mut topic_path = try!(Self::parse(filter));
+        topic_path.path = String::from(path);
+        topic_path.share = Some(String::from(group));
+        Ok(topic_path)
+    }
+
+    /// Parses a topic name, which must not contain any wildcard character.
+    pub fn from_name<T: AsRef<str>>(path: T) -> Result<TopicPath> {
+        let path = path.as_ref();
+        if path.contains('+') || path.contains('#') {
+            return Err(Error::TopicNameMustNotContainWildcard);
+        }
+        Self::parse(path)
+    }
+
+    fn parse(path: &str) -> Result<TopicPath> {
+        if path.is_empty() {
+            return Err(Error::TopicPathMustNotBeEmpty);
+        }
+        if path.len() > MAX_TOPIC_PATH_LEN {
+            return Err(Error::TopicPathTooLong);
+        }
+        if path.contains('\0') {
+            return Err(Error::TopicPathMustNotContainNul);
+        }
+
+        let levels = path.split(TOPIC_PATH_DELIMITER).count();
+        let mut topics = Vec::with_capacity(levels);
+        for (index, topic) in path.split(TOPIC_PATH_DELIMITER).enumerate() {
+            topics.push(match topic {
                 "+" => Topic::SingleWildcard,
-                "#" => Topic::MultiWildcard,
+                "#" => {
+                    if index != levels - 1 {
+                        return Err(Error::TopicFilterMultiWildcardMustBeLast);
+                    }
+                    Topic::MultiWildcard
+                },
                 "" => Topic::Blank,
                 _ => {
                     if !Topic::validate(topic) {
-                        valid = false;
+                        return Err(Error::TopicFilterWildcardMustOccupyLevel);
                     }
                     if topic.chars().nth(0) == Some('$') {
                         Topic::System(String::from(topic))
@@ -131,12 +227,9 @@ impl TopicPath {
                         Topic::Normal(String::from(topic))
                     }
                 }
-            }
-        }).collect();
-
-        if !valid {
-            return Err(Error::InvalidTopicPath);
+            });
         }
+
         // check for wildcards
         let wildcards = topics.iter().any(|topic| {
             match *topic {
@@ -146,9 +239,10 @@ impl TopicPath {
         });
 
         Ok(TopicPath {
-            path: String::from(path.as_ref()),
+            path: String::from(path),
             topics: topics,
-            wildcards: wildcards
+            wildcards: wildcards,
+            share: None
         })
     }
 }
@@ -161,15 +255,19 @@ impl IntoIterator for TopicPath {
     }
 }
 
-impl<'a> From<&'a str> for TopicPath {
-    fn from(str: &'a str) -> TopicPath {
-        Self::from_str(str).unwrap()
+impl<'a> TryFrom<&'a str> for TopicPath {
+    type Error = Error;
+
+    fn try_from(str: &'a str) -> Result<TopicPath> {
+        Self::from_str(str)
     }
 }
 
-impl From<String> for TopicPath {
-    fn from(path: String) -> TopicPath {
-        Self::from_str(path).unwrap()
+impl TryFrom<String> for TopicPath {
+    type Error = Error;
+
+    fn try_from(path: String) -> Result<TopicPath> {
+        Self::from_str(path)
     }
 }
 
@@ -179,6 +277,22 @@ impl Into<String> for TopicPath {
     }
 }
 
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for TopicPath {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.path)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for TopicPath {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> ::std::result::Result<TopicPath, D::Error> {
+        use serde::de::Error;
+        let path = String::deserialize(deserializer)?;
+        TopicPath::from_str(path).map_err(D::Error::custom)
+    }
+}
+
 pub trait ToTopicPath {
     fn to_topic_path(&self) -> Result<TopicPath>;
 
@@ -199,7 +313,11 @@ impl ToTopicPath for TopicPath {
 
 impl ToTopicPath for String {
     fn to_topic_path(&self) -> Result<TopicPath> {
-        TopicPath::from_str(self.clone())
+        TopicPath::from_str(self)
+    }
+
+    fn to_topic_name(&self) -> Result<TopicPath> {
+        TopicPath::from_name(self)
     }
 }
 
@@ -207,16 +325,22 @@ impl<'a> ToTopicPath for &'a str {
     fn to_topic_path(&self) -> Result<TopicPath> {
         TopicPath::from_str(*self)
     }
+
+    fn to_topic_name(&self) -> Result<TopicPath> {
+        TopicPath::from_name(*self)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{TopicPath, Topic};
+    use std::convert::TryFrom;
+    use super::{TopicPath, Topic, ToTopicPath};
+    use Error;
 
     #[test]
     fn topic_path_test() {
         let path = "/$SYS/test/+/#";
-        let topic = TopicPath::from(path);
+        let topic = TopicPath::try_from(path).unwrap();
         let mut iter = topic.into_iter();
         assert_eq!(iter.next().unwrap(), Topic::Blank);
         assert_eq!(iter.next().unwrap(), Topic::System("$SYS".to_string()));
@@ -227,11 +351,11 @@ mod test {
 
     #[test]
     fn wildcards_test() {
-        let topic = TopicPath::from("/a/b/c");
+        let topic = TopicPath::try_from("/a/b/c").unwrap();
         assert!(!topic.wildcards);
-        let topic = TopicPath::from("/a/+/c");
+        let topic = TopicPath::try_from("/a/+/c").unwrap();
         assert!(topic.wildcards);
-        let topic = TopicPath::from("/a/b/#");
+        let topic = TopicPath::try_from("/a/b/#").unwrap();
         assert!(topic.wildcards);
     }
 
@@ -241,4 +365,117 @@ mod test {
         assert!(TopicPath::from_str("wro#ng").is_err());
         assert!(TopicPath::from_str("w/r/o/n/g+").is_err());
     }
+
+    #[test]
+    fn topic_filter_spec_test() {
+        // multi-level wildcard
+        assert!(TopicPath::from_str("sport/tennis/player1/#").is_ok());
+        assert!(TopicPath::from_str("sport/tennis/#").is_ok());
+        assert!(TopicPath::from_str("#").is_ok());
+        assert!(TopicPath::from_str("sport/#").is_ok());
+        assert!(match TopicPath::from_str("sport/tennis#") {
+            Err(Error::TopicFilterWildcardMustOccupyLevel) => true,
+            _ => false
+        });
+        assert!(match TopicPath::from_str("sport/tennis/#/ranking") {
+            Err(Error::TopicFilterMultiWildcardMustBeLast) => true,
+            _ => false
+        });
+        // single-level wildcard
+        assert!(TopicPath::from_str("+").is_ok());
+        assert!(TopicPath::from_str("+/tennis/#").is_ok());
+        assert!(TopicPath::from_str("sport/+/player1").is_ok());
+        assert!(TopicPath::from_str("/+").is_ok());
+        assert!(TopicPath::from_str("+/+").is_ok());
+        assert!(match TopicPath::from_str("sport+") {
+            Err(Error::TopicFilterWildcardMustOccupyLevel) => true,
+            _ => false
+        });
+        // system topics
+        assert!(TopicPath::from_str("$SYS/#").is_ok());
+    }
+
+    #[test]
+    fn topic_name_spec_test() {
+        assert!("sport/tennis/player1".to_topic_name().is_ok());
+        assert!("/finance".to_topic_name().is_ok());
+        assert!("/".to_topic_name().is_ok());
+        assert!("ACCOUNTS".to_topic_name().is_ok());
+        assert!(match "sport/tennis+".to_topic_name() {
+            Err(Error::TopicNameMustNotContainWildcard) => true,
+            _ => false
+        });
+        assert!(match "sport/#".to_string().to_topic_name() {
+            Err(Error::TopicNameMustNotContainWildcard) => true,
+            _ => false
+        });
+    }
+
+    #[test]
+    fn topic_path_limits_test() {
+        assert!(match TopicPath::from_str("") {
+            Err(Error::TopicPathMustNotBeEmpty) => true,
+            _ => false
+        });
+        assert!(match TopicPath::from_str("a/\u{0}/b") {
+            Err(Error::TopicPathMustNotContainNul) => true,
+            _ => false
+        });
+        assert!(TopicPath::from_str("a".repeat(65535)).is_ok());
+        assert!(match TopicPath::from_str("a".repeat(65536)) {
+            Err(Error::TopicPathTooLong) => true,
+            _ => false
+        });
+        assert!(TopicPath::try_from("a/#/b").is_err());
+        assert!(TopicPath::try_from(String::new()).is_err());
+    }
+
+    #[test]
+    fn shared_subscription_test() {
+        let topic = TopicPath::from_str("$share/consumers/sensors/#").unwrap();
+        assert_eq!(topic.share(), Some("consumers"));
+        assert_eq!(topic.filter(), "sensors/#");
+        assert_eq!(topic.path, "$share/consumers/sensors/#");
+        assert_eq!(topic.get(0), Some(&Topic::Normal("sensors".to_string())));
+        assert!(topic.wildcards);
+        assert!(topic.matches(&"sensors/temp".to_topic_name().unwrap()));
+        assert!(!topic.matches(&"$share/consumers/sensors/temp".to_topic_name().unwrap()));
+
+        let topic = TopicPath::from_str("sensors/#").unwrap();
+        assert_eq!(topic.share(), None);
+        assert_eq!(topic.filter(), "sensors/#");
+
+        assert!(TopicPath::from_str("$share/group").is_err());
+        assert!(TopicPath::from_str("$share//a").is_err());
+        assert!(TopicPath::from_str("$share/gr+oup/a").is_err());
+        assert!(TopicPath::from_str("$share/group/").is_err());
+        assert!(TopicPath::from_str("$share/group/a/#/b").is_err());
+        // not a share prefix for topic names
+        assert_eq!("$share/group/a".to_topic_name().unwrap().share(), None);
+    }
+
+    #[test]
+    fn matches_test() {
+        fn matches(filter: &str, name: &str) -> bool {
+            TopicPath::from_str(filter).unwrap().matches(&name.to_topic_name().unwrap())
+        }
+        assert!(matches("sport/tennis/player1/#", "sport/tennis/player1"));
+        assert!(matches("sport/tennis/player1/#", "sport/tennis/player1/ranking"));
+        assert!(matches("sport/tennis/player1/#", "sport/tennis/player1/score/wimbledon"));
+        assert!(matches("sport/#", "sport"));
+        assert!(matches("sport/tennis/+", "sport/tennis/player1"));
+        assert!(!matches("sport/tennis/+", "sport/tennis/player1/ranking"));
+        assert!(!matches("sport/+", "sport"));
+        assert!(matches("sport/+", "sport/"));
+        assert!(matches("+/+", "/finance"));
+        assert!(matches("/+", "/finance"));
+        assert!(!matches("+", "/finance"));
+        assert!(matches("#", "a/b/c"));
+        assert!(!matches("#", "$SYS/monitor/Clients"));
+        assert!(!matches("+/monitor/Clients", "$SYS/monitor/Clients"));
+        assert!(matches("$SYS/#", "$SYS/monitor/Clients"));
+        assert!(matches("$SYS/monitor/+", "$SYS/monitor/Clients"));
+        assert!(!matches("a/b", "a/b/c"));
+        assert!(!matches("a/b/c", "a/b"));
+    }
 }
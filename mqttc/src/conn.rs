@@ -1,17 +1,20 @@
-use mqtt3::{MqttRead, MqttWrite};
-use std::io::{self, Read, Write};
+use mqtt3::{self, MqttRead, MqttWrite, Packet};
+use std::io::{self, Cursor, Read, Write};
 use std::net::Shutdown;
 use std::time::Duration;
 use netopt::{NetworkStream};
 
 pub struct Connection {
-    stream: NetworkStream
+    stream: NetworkStream,
+    // bytes of packets that haven't arrived in full yet
+    buffer: Vec<u8>
 }
 
 impl Connection {
     pub fn new(stream: NetworkStream) -> io::Result<Connection> {
         Ok(Connection {
-            stream: stream
+            stream: stream,
+            buffer: Vec::new()
         })
     }
 
@@ -22,6 +25,41 @@ impl Connection {
     pub fn terminate(&self) -> io::Result<()> {
         self.stream.shutdown(Shutdown::Both)
     }
+
+    /// Reads the next packet. Bytes read before a timeout are kept, so the
+    /// read can be tried again without losing part of a packet.
+    pub fn read_packet(&mut self) -> mqtt3::Result<Packet> {
+        loop {
+            if let Some(len) = packet_len(&self.buffer)? {
+                return Cursor::new(self.buffer.drain(..len).collect::<Vec<u8>>()).read_packet();
+            }
+            let mut buf = [0; 4096];
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(mqtt3::Error::UnexpectedEof),
+                Ok(n) => self.buffer.extend_from_slice(&buf[..n]),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(mqtt3::Error::Io(err))
+            }
+        }
+    }
+}
+
+/// Length of the first packet in `buf`, `None` until all of it is there
+pub fn packet_len(buf: &[u8]) -> mqtt3::Result<Option<usize>> {
+    let mut len = 0;
+    let mut mult = 1;
+    for (i, byte) in buf.iter().enumerate().skip(1) {
+        if i > 4 {
+            return Err(mqtt3::Error::MalformedRemainingLength);
+        }
+        len += (*byte as usize & 0x7F) * mult;
+        mult *= 0x80;
+        if byte & 0x80 == 0 {
+            let total = i + 1 + len;
+            return Ok(if buf.len() >= total { Some(total) } else { None });
+        }
+    }
+    Ok(None)
 }
 
 impl Write for Connection {
@@ -36,7 +74,13 @@ impl Write for Connection {
 
 impl Read for Connection {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.stream.read(buf)
+        if self.buffer.is_empty() {
+            return self.stream.read(buf);
+        }
+        let n = buf.len().min(self.buffer.len());
+        buf[..n].copy_from_slice(&self.buffer[..n]);
+        self.buffer.drain(..n);
+        Ok(n)
     }
 }
 
@@ -20,6 +20,7 @@ pub enum Error {
     ProtocolViolation,
     Disconnected,
     Timeout,
+    InvalidUrl(String),
     UnhandledPuback(PacketIdentifier),
     UnhandledPubrec(PacketIdentifier),
     UnhandledPubrel(PacketIdentifier),
@@ -60,6 +61,7 @@ impl fmt::Display for Error {
             Error::UnhandledPubrec(PacketIdentifier(pi)) => fmt::write(f, format_args!("{:?}", pi)),
             Error::UnhandledPubrel(PacketIdentifier(pi)) => fmt::write(f, format_args!("{:?}", pi)),
             Error::UnhandledPubcomp(PacketIdentifier(pi)) => fmt::write(f, format_args!("{:?}", pi)),
+            Error::InvalidUrl(ref reason) => write!(f, "Invalid URL {}", reason),
             Error::ConnectionRefused(crc) => fmt::write(f, format_args!("{:?}", crc)),
             Error::Storage(ref err) => write!(f, "Storage error: {:?}", err),
             Error::Mqtt(ref err) => write!(f, "MQTT error: {:?}", err),
@@ -84,6 +86,7 @@ impl error::Error for Error {
             Error::ProtocolViolation => "ProtocolViolation",
             Error::Disconnected => "Disconnected",
             Error::Timeout => "Timeout",
+            Error::InvalidUrl(_) => "InvalidUrl",
             Error::UnhandledPuback(_) => "UnhandledPuback",
             Error::UnhandledPubrec(_) => "UnhandledPubrec",
             Error::UnhandledPubrel(_) => "UnhandledPubrel",
@@ -8,7 +8,9 @@ mod error;
 mod sub;
 mod client;
 mod conn;
+mod url;
 pub mod store;
+pub mod mock;
 
 pub use conn::{
     Connection
@@ -20,10 +22,17 @@ pub use error::{
 };
 
 pub use sub::{
+    Subscription,
     ToSubTopics,
     ToUnSubTopics
 };
 
+pub use url::{
+    BrokerUrl,
+    Scheme,
+    ToBrokerUrl
+};
+
 pub use client::{
     Client,
     ClientOptions
@@ -0,0 +1,254 @@
+//! Scripted broker for testing `Client` without sockets.
+//!
+//! ```ignore
+//! let mut broker = MockBroker::new();
+//! broker.expect_connect().connack(false)
+//!       .expect_publish(QoS::AtLeastOnce).delay(Duration::from_millis(10)).reply(Packet::Puback(PacketIdentifier(1)));
+//! let mut netopt = NetworkOptions::new();
+//! netopt.attach(broker.stream());
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Cursor, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use mqtt3::{MqttRead, MqttWrite, Packet, QoS, Connack, ConnectReturnCode};
+use netopt::mock::{MockStream, MockPeer};
+use conn::packet_len;
+
+enum Step {
+    Expect(String, Box<dyn Fn(&Packet) -> bool + Send>),
+    Reply(Packet),
+    Bytes(Vec<u8>),
+    Delay(Duration),
+    Timeout,
+    Disconnect
+}
+
+impl fmt::Debug for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Step::Expect(ref name, _) => write!(f, "expect {}", name),
+            Step::Reply(ref packet) => write!(f, "reply {:?}", packet),
+            Step::Bytes(ref bytes) => write!(f, "reply {} bytes", bytes.len()),
+            Step::Delay(dur) => write!(f, "delay {:?}", dur),
+            Step::Timeout => write!(f, "timeout"),
+            Step::Disconnect => write!(f, "disconnect")
+        }
+    }
+}
+
+struct Script {
+    steps: VecDeque<Step>,
+    received: Vec<u8>,
+    packets: Vec<Packet>,
+    connections: usize,
+    connected: bool
+}
+
+/// Broker playing a script of expected and answered packets.
+///
+/// Steps run whenever the client reads: expected packets are checked
+/// against what the client wrote, replies become its input. A mismatch, or
+/// the client waiting for input while a packet is expected, panics.
+#[derive(Clone)]
+pub struct MockBroker {
+    script: Arc<Mutex<Script>>
+}
+
+impl MockBroker {
+    pub fn new() -> MockBroker {
+        MockBroker {
+            script: Arc::new(Mutex::new(Script {
+                steps: VecDeque::new(),
+                received: Vec::new(),
+                packets: Vec::new(),
+                connections: 0,
+                connected: false
+            }))
+        }
+    }
+
+    /// Stream to attach to `NetworkOptions`, every connect starts a new connection
+    pub fn stream(&self) -> MockStream {
+        MockStream::with_peer(self.clone())
+    }
+
+    /// Expects a packet accepted by `matches`, `name` describes it on failure
+    pub fn expect<F>(&mut self, name: &str, matches: F) -> &mut MockBroker
+        where F: Fn(&Packet) -> bool + Send + 'static
+    {
+        self.push(Step::Expect(name.to_owned(), Box::new(matches)))
+    }
+
+    /// Expects exactly `packet`
+    pub fn expect_packet(&mut self, packet: Packet) -> &mut MockBroker {
+        let name = format!("{:?}", packet);
+        self.expect(&name, move |received| *received == packet)
+    }
+
+    pub fn expect_connect(&mut self) -> &mut MockBroker {
+        self.expect("Connect", |packet| matches!(*packet, Packet::Connect(_)))
+    }
+
+    pub fn expect_publish(&mut self, qos: QoS) -> &mut MockBroker {
+        self.expect(&format!("Publish {:?}", qos), move |packet| match *packet {
+            Packet::Publish(ref publish) => publish.qos == qos,
+            _ => false
+        })
+    }
+
+    pub fn expect_subscribe(&mut self) -> &mut MockBroker {
+        self.expect("Subscribe", |packet| matches!(*packet, Packet::Subscribe(_)))
+    }
+
+    pub fn reply(&mut self, packet: Packet) -> &mut MockBroker {
+        self.push(Step::Reply(packet))
+    }
+
+    /// Replies with raw bytes, such as part of a packet
+    pub fn reply_bytes(&mut self, bytes: Vec<u8>) -> &mut MockBroker {
+        self.push(Step::Bytes(bytes))
+    }
+
+    /// Accepts the connection
+    pub fn connack(&mut self, session_present: bool) -> &mut MockBroker {
+        self.reply(Packet::Connack(Connack {
+            session_present: session_present,
+            code: ConnectReturnCode::Accepted
+        }))
+    }
+
+    /// Holds back the next step. Reads with a shorter timeout time out and
+    /// the rest of the delay is kept for the next read.
+    pub fn delay(&mut self, dur: Duration) -> &mut MockBroker {
+        self.push(Step::Delay(dur))
+    }
+
+    /// Times out the next read right away
+    pub fn timeout(&mut self) -> &mut MockBroker {
+        self.push(Step::Timeout)
+    }
+
+    /// Closes the connection, until the client connects again
+    pub fn disconnect(&mut self) -> &mut MockBroker {
+        self.push(Step::Disconnect)
+    }
+
+    /// Packets received so far, across connections
+    pub fn received(&self) -> Vec<Packet> {
+        self.script.lock().unwrap().packets.clone()
+    }
+
+    pub fn connections(&self) -> usize {
+        self.script.lock().unwrap().connections
+    }
+
+    /// Checks the packets written since the last read and panics unless
+    /// the whole script has been played
+    pub fn assert_done(&self) {
+        let mut script = self.script.lock().unwrap();
+        script.check_expected();
+        assert!(script.steps.is_empty(), "mock broker script not finished: {:?}", script.steps);
+    }
+
+    fn push(&mut self, step: Step) -> &mut MockBroker {
+        self.script.lock().unwrap().steps.push_back(step);
+        self
+    }
+}
+
+impl Default for MockBroker {
+    fn default() -> MockBroker {
+        MockBroker::new()
+    }
+}
+
+impl MockPeer for MockBroker {
+    fn connect(&mut self) -> io::Result<()> {
+        let mut script = self.script.lock().unwrap();
+        script.connections += 1;
+        script.connected = true;
+        script.received.clear();
+        Ok(())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut script = self.script.lock().unwrap();
+        if !script.connected {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "mock broker disconnected"));
+        }
+        script.received.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn read(&mut self, timeout: Option<Duration>) -> io::Result<Vec<u8>> {
+        let mut script = self.script.lock().unwrap();
+        let mut replies = Cursor::new(Vec::new());
+        while script.connected {
+            script.check_expected();
+            let step = match script.steps.pop_front() {
+                Some(step) => step,
+                None => break
+            };
+            match step {
+                Step::Reply(packet) => {
+                    replies.write_packet(&packet).unwrap();
+                },
+                Step::Bytes(bytes) => {
+                    replies.write_all(&bytes).unwrap();
+                },
+                _ if !replies.get_ref().is_empty() => {
+                    // deliver what's there before acting on the next step
+                    script.steps.push_front(step);
+                    break;
+                },
+                Step::Expect(name, _) => {
+                    panic!("mock broker expected {}, but the client is waiting for input", name);
+                },
+                Step::Delay(dur) => match timeout {
+                    Some(timeout) if timeout < dur => {
+                        script.steps.push_front(Step::Delay(dur - timeout));
+                        drop(script);
+                        thread::sleep(timeout);
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "mock broker delay"));
+                    },
+                    _ => {
+                        // let other clones of the broker be inspected meanwhile
+                        drop(script);
+                        thread::sleep(dur);
+                        script = self.script.lock().unwrap();
+                    }
+                },
+                Step::Timeout => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "mock broker timeout"));
+                },
+                Step::Disconnect => {
+                    script.connected = false;
+                }
+            }
+        }
+        // closed connection or finished script read as EOF
+        Ok(replies.into_inner())
+    }
+}
+
+impl Script {
+    /// Matches complete packets the client has written against expected steps
+    fn check_expected(&mut self) {
+        while let Some(len) = packet_len(&self.received).unwrap() {
+            let packet = Cursor::new(self.received.drain(..len).collect::<Vec<u8>>()).read_packet()
+                .unwrap_or_else(|err| panic!("mock broker received a malformed packet: {:?}", err));
+            self.packets.push(packet.clone());
+            match self.steps.pop_front() {
+                Some(Step::Expect(ref name, ref matches)) => {
+                    assert!(matches(&packet), "mock broker expected {}, received {:?}", name, packet);
+                },
+                Some(step) => panic!("mock broker received {:?} while about to {:?}", packet, step),
+                None => panic!("mock broker received {:?} after the script ended", packet)
+            }
+        }
+    }
+}
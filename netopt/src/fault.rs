@@ -0,0 +1,287 @@
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tcp::NetworkStream;
+
+/// Faults to inject into connections, drawn from an RNG seeded with `seed`.
+///
+/// The n-th connection made with the same options uses `seed + n`, so a
+/// failing run is reproduced by running it again with the same seed.
+#[derive(Debug, Clone)]
+pub struct FaultOptions {
+    seed: u64,
+    latency: Option<Duration>,
+    bandwidth: Option<u64>,
+    short_io: f64,
+    corruption: f64,
+    stall: Option<(f64, Duration)>,
+    reset: f64,
+    connections: Arc<AtomicUsize>
+}
+
+impl FaultOptions {
+    /// No faults until some are set
+    pub fn new(seed: u64) -> FaultOptions {
+        FaultOptions {
+            seed: seed,
+            latency: None,
+            bandwidth: None,
+            short_io: 0.0,
+            corruption: 0.0,
+            stall: None,
+            reset: 0.0,
+            connections: Arc::new(AtomicUsize::new(0))
+        }
+    }
+
+    /// Delays every read and flush
+    pub fn set_latency(&mut self, latency: Duration) -> &mut FaultOptions {
+        self.latency = Some(latency); self
+    }
+
+    /// Limits throughput in both directions together, in bytes per second
+    pub fn set_bandwidth(&mut self, bytes_per_sec: u64) -> &mut FaultOptions {
+        self.bandwidth = Some(bytes_per_sec); self
+    }
+
+    /// Chance of a read or write moving fewer bytes than it could
+    pub fn set_short_io(&mut self, probability: f64) -> &mut FaultOptions {
+        self.short_io = probability; self
+    }
+
+    /// Chance of each byte read or written having a bit flipped
+    pub fn set_corruption(&mut self, probability: f64) -> &mut FaultOptions {
+        self.corruption = probability; self
+    }
+
+    /// Chance of a read or write hanging for `duration` first
+    pub fn set_stalls(&mut self, probability: f64, duration: Duration) -> &mut FaultOptions {
+        self.stall = Some((probability, duration)); self
+    }
+
+    /// Chance of a read or write resetting the connection
+    pub fn set_resets(&mut self, probability: f64) -> &mut FaultOptions {
+        self.reset = probability; self
+    }
+
+    /// Wraps the next connection
+    pub fn wrap(&self, stream: NetworkStream) -> FaultStream {
+        let n = self.connections.fetch_add(1, Ordering::SeqCst);
+        FaultStream {
+            inner: stream,
+            options: self.clone(),
+            rng: Rng(self.seed.wrapping_add(n as u64)),
+            reset: false,
+            started: Instant::now(),
+            transferred: 0
+        }
+    }
+}
+
+/// Stream over a `NetworkStream` that misbehaves as its `FaultOptions` say.
+pub struct FaultStream {
+    inner: NetworkStream,
+    options: FaultOptions,
+    rng: Rng,
+    reset: bool,
+    started: Instant,
+    transferred: u64
+}
+
+impl FaultStream {
+    pub fn get_ref(&self) -> &NetworkStream {
+        &self.inner
+    }
+
+    /// Latency is left to the callers, stalls and resets happen here
+    fn before_io(&mut self) -> io::Result<()> {
+        if self.reset {
+            return Err(reset_error());
+        }
+        if let Some((probability, duration)) = self.options.stall {
+            if self.rng.chance(probability) {
+                thread::sleep(duration);
+            }
+        }
+        if self.rng.chance(self.options.reset) {
+            self.reset = true;
+            let _ = self.inner.shutdown(Shutdown::Both);
+            return Err(reset_error());
+        }
+        Ok(())
+    }
+
+    fn chunk(&mut self, len: usize) -> usize {
+        if len > 1 && self.rng.chance(self.options.short_io) {
+            1 + self.rng.below(len - 1)
+        } else {
+            len
+        }
+    }
+
+    fn corrupt(&mut self, buf: &mut [u8]) {
+        if self.options.corruption > 0.0 {
+            for byte in buf.iter_mut() {
+                if self.rng.chance(self.options.corruption) {
+                    *byte ^= 1 << self.rng.below(8);
+                }
+            }
+        }
+    }
+
+    /// Sleeps until the bytes moved so far fit in the bandwidth
+    fn throttle(&mut self, len: usize) {
+        if let Some(bandwidth) = self.options.bandwidth {
+            self.transferred += len as u64;
+            let due = Duration::from_secs_f64(self.transferred as f64 / bandwidth.max(1) as f64);
+            let elapsed = self.started.elapsed();
+            if due > elapsed {
+                thread::sleep(due - elapsed);
+            }
+        }
+    }
+
+    fn delay(&self) {
+        if let Some(latency) = self.options.latency {
+            thread::sleep(latency);
+        }
+    }
+}
+
+impl Read for FaultStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.before_io()?;
+        self.delay();
+        let len = self.chunk(buf.len());
+        let n = self.inner.read(&mut buf[..len])?;
+        self.corrupt(&mut buf[..n]);
+        self.throttle(n);
+        Ok(n)
+    }
+}
+
+impl Write for FaultStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.before_io()?;
+        let len = self.chunk(buf.len());
+        let mut data = buf[..len].to_vec();
+        self.corrupt(&mut data);
+        let n = self.inner.write(&data)?;
+        self.throttle(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.reset {
+            return Err(reset_error());
+        }
+        self.delay();
+        self.inner.flush()
+    }
+}
+
+fn reset_error() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "connection reset by fault injection")
+}
+
+/// SplitMix64, so a seed gives the same faults on every platform and release
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use std::time::{Duration, Instant};
+    use mock::MockStream;
+    use tcp::{NetworkOptions, NetworkStream};
+    use super::FaultOptions;
+
+    fn read_all(options: &FaultOptions, data: Vec<u8>) -> (Vec<u8>, usize) {
+        let mut stream = options.wrap(NetworkStream::Mock(MockStream::with_vec(data)));
+        let mut received = Vec::new();
+        let mut reads = 0;
+        let mut buf = [0; 64];
+        loop {
+            match stream.read(&mut buf).unwrap() {
+                0 => return (received, reads),
+                n => {
+                    received.extend_from_slice(&buf[..n]);
+                    reads += 1;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fault_short_io_test() {
+        let mut options = FaultOptions::new(1);
+        options.set_short_io(1.0);
+        let data: Vec<u8> = (0..64).collect();
+        let (received, reads) = read_all(&options, data.clone());
+        assert_eq!(received, data);
+        assert!(reads > 1);
+    }
+
+    #[test]
+    fn fault_corruption_seed_test() {
+        let mut options = FaultOptions::new(42);
+        options.set_corruption(0.1);
+        let data = vec![0; 256];
+        let (first, _) = read_all(&options, data.clone());
+        assert!(first != data);
+        assert_eq!(first.len(), data.len());
+
+        // same seed, same damage
+        let mut again = FaultOptions::new(42);
+        again.set_corruption(0.1);
+        assert_eq!(read_all(&again, data.clone()).0, first);
+        let mut other = FaultOptions::new(43);
+        other.set_corruption(0.1);
+        assert!(read_all(&other, data).0 != first);
+    }
+
+    #[test]
+    fn fault_reset_test() {
+        let mut options = FaultOptions::new(7);
+        options.set_resets(1.0);
+        let mut stream = options.wrap(NetworkStream::Mock(MockStream::new()));
+        assert_eq!(stream.write(&[1]).unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(stream.flush().unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(stream.read(&mut [0; 1]).unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn fault_bandwidth_test() {
+        let mut faults = FaultOptions::new(3);
+        faults.set_bandwidth(10_000).set_latency(Duration::from_millis(5));
+        let mut options = NetworkOptions::new();
+        options.attach(MockStream::new()).faults(faults);
+
+        let start = Instant::now();
+        let mut stream = options.connect("127.0.0.1:1883").unwrap();
+        stream.write_all(&[0; 1000]).unwrap();
+        stream.flush().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -1,56 +1,140 @@
-#[cfg(feature = "ssl")]
+// rustls is used when both TLS backends are enabled, so feature unification
+// and `--all-features` still build
+#[cfg(all(feature = "ssl", not(feature = "rustls")))]
 extern crate openssl;
+#[cfg(feature = "rustls")]
+extern crate rustls;
+#[cfg(feature = "rustls")]
+extern crate rustls_native_certs;
+#[cfg(feature = "rustls")]
+extern crate webpki_roots;
+#[cfg(feature = "websocket")]
+extern crate tungstenite;
+#[cfg(unix)]
+extern crate libc;
+extern crate socket2;
+extern crate base64;
 
-#[cfg(feature = "ssl")]
+#[cfg(all(feature = "ssl", not(feature = "rustls")))]
+mod ssl;
+#[cfg(feature = "rustls")]
+#[path = "ssl_rustls.rs"]
 mod ssl;
 mod tcp;
+mod proxy;
+mod socket;
+mod fault;
+#[cfg(any(feature = "ssl", feature = "rustls"))]
+mod tls;
+#[cfg(feature = "websocket")]
+mod ws;
+#[cfg(unix)]
+mod unix;
 pub mod mock;
 
 pub use tcp::{
     NetworkOptions,
+    ToHostAddrs,
     NetworkListener,
     NetworkStream,
     NetworkWriter,
     NetworkReader
 };
 
-#[cfg(feature = "ssl")]
+pub use socket::Keepalive;
+
+pub use fault::{
+    FaultOptions,
+    FaultStream
+};
+
+pub use proxy::{
+    Proxy,
+    ProxyKind
+};
+
+#[cfg(unix)]
+pub use unix::PeerCred;
+
+#[cfg(feature = "websocket")]
+pub use ws::{
+    WsOptions,
+    WsStream
+};
+
+#[cfg(any(feature = "ssl", feature = "rustls"))]
+pub use tls::{
+    SslContextBuilder,
+    TlsHandle,
+    TlsVersion,
+    VerifyMode
+};
+
 pub use ssl::{
     SslContext,
     SslStream,
     SslError
 };
 
-#[cfg(not(feature = "ssl"))]
-pub mod ssl {
-    use mock::MockStream;
-    use std::net::TcpStream;
-    use std::io;
+#[cfg(not(any(feature = "ssl", feature = "rustls")))]
+mod ssl {
+    //! Without a TLS backend these types can't be constructed, so
+    //! `NetworkOptions::tls` can't be called.
+
     use std::error::Error;
-    use std::path::Path;
+    use std::fmt;
+    use std::io::{self, Read, Write};
+    use std::net::TcpStream;
+
+    #[derive(Debug, Clone)]
+    pub enum SslContext {}
 
-    pub type SslStream = MockStream;
-    pub type SslError = Error + 'static;
+    pub enum SslStream {}
 
-    #[derive(Debug, Clone, Default)]
-    pub struct SslContext;
+    #[derive(Debug)]
+    pub enum SslError {}
 
     impl SslContext {
-        pub fn new(_: SslContext) -> Self {
-            panic!("ssl disabled");
+        pub fn set_verify(&mut self, _: bool) -> &mut SslContext {
+            match *self {}
         }
 
-        pub fn with_cert_and_key<C, K, E>(_: C, _: K) -> Result<SslContext, E>
-        where C: AsRef<Path>, K: AsRef<Path>, E: Error + 'static {
-            panic!("ssl disabled");
+        pub fn accept(&self, _: TcpStream) -> Result<SslStream, io::Error> {
+            match *self {}
         }
 
-        pub fn accept(&self, _: TcpStream) -> Result<SslStream, io::Error> {
-            panic!("ssl disabled");
+        pub fn connect(&self, _: TcpStream, _: &str) -> Result<SslStream, io::Error> {
+            match *self {}
+        }
+    }
+
+    impl SslStream {
+        pub fn get_ref(&self) -> &TcpStream {
+            match *self {}
         }
+    }
 
-        pub fn connect(&self, _: TcpStream) -> Result<SslStream, io::Error> {
-            panic!("ssl disabled");
+    impl Read for SslStream {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            match *self {}
         }
     }
+
+    impl Write for SslStream {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            match *self {}
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            match *self {}
+        }
+    }
+
+    impl fmt::Display for SslError {
+        fn fmt(&self, _: &mut fmt::Formatter) -> fmt::Result {
+            match *self {}
+        }
+    }
+
+    impl Error for SslError {}
 }
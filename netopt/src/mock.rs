@@ -1,31 +1,62 @@
 use std::io::{self, Cursor, Read, Write};
-use std::net::{SocketAddr, Shutdown};
+use std::net::{SocketAddr, SocketAddrV4, Ipv4Addr, Shutdown};
 use std::time::Duration;
 use std::sync::{Mutex, Arc};
 
 pub type MockCursor = Cursor<Vec<u8>>;
 
+/// Other end of a `MockStream`, asked for more input whenever everything
+/// given so far has been read.
+pub trait MockPeer: Send {
+    /// The stream was handed out by `NetworkOptions::connect` again
+    fn connect(&mut self) -> io::Result<()>;
+
+    /// Bytes written to the stream
+    fn write(&mut self, buf: &[u8]) -> io::Result<()>;
+
+    /// Next bytes for the stream to read, waiting no longer than the read
+    /// timeout. Nothing means the connection was closed.
+    fn read(&mut self, timeout: Option<Duration>) -> io::Result<Vec<u8>>;
+}
+
 #[derive(Clone)]
 pub struct MockStream {
     reader: Arc<Mutex<MockCursor>>,
-    writer: Arc<Mutex<MockCursor>>
+    writer: Arc<Mutex<MockCursor>>,
+    peer: Option<Arc<Mutex<Box<dyn MockPeer>>>>,
+    read_timeout: Arc<Mutex<Option<Duration>>>
 }
 
 impl MockStream {
     pub fn new() -> MockStream {
-        MockStream {
-            reader: Arc::new(Mutex::new(MockCursor::new(Vec::new()))),
-            writer: Arc::new(Mutex::new(MockCursor::new(Vec::new())))
-        }
+        MockStream::with_vec(Vec::new())
     }
 
     pub fn with_vec(vec: Vec<u8>) -> MockStream {
         MockStream {
             reader: Arc::new(Mutex::new(MockCursor::new(vec))),
-            writer: Arc::new(Mutex::new(MockCursor::new(Vec::new())))
+            writer: Arc::new(Mutex::new(MockCursor::new(Vec::new()))),
+            peer: None,
+            read_timeout: Arc::new(Mutex::new(None))
         }
     }
 
+    /// Stream whose input comes from `peer`, which also sees everything written
+    pub fn with_peer<P: MockPeer + 'static>(peer: P) -> MockStream {
+        let mut stream = MockStream::new();
+        stream.peer = Some(Arc::new(Mutex::new(Box::new(peer))));
+        stream
+    }
+
+    /// Starts a new connection, dropping input that wasn't read
+    pub fn connect(&self) -> io::Result<MockStream> {
+        if let Some(ref peer) = self.peer {
+            peer.lock().unwrap().connect()?;
+            self.reader.lock().unwrap().get_mut().clear();
+        }
+        Ok(self.clone())
+    }
+
     pub fn take_vec(&mut self) -> Vec<u8> {
         let mut cursor = self.writer.lock().unwrap();
         let vec = cursor.get_ref().to_vec();
@@ -58,32 +89,37 @@ impl MockStream {
 
 impl MockStream {
     pub fn try_clone(&self) -> io::Result<MockStream> {
-        unimplemented!()
+        Ok(self.clone())
     }
 
     pub fn peer_addr(&self) -> io::Result<SocketAddr> {
-        unimplemented!()
+        Ok(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127,0,0,1), 80)))
     }
 
     pub fn shutdown(&self, _: Shutdown) -> io::Result<()> {
-        unimplemented!()
+        Ok(())
     }
 
-    pub fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
-        unimplemented!()
+    /// Passed on to the peer, which decides when a read times out
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        *self.read_timeout.lock().unwrap() = dur;
+        Ok(())
     }
 
     pub fn set_write_timeout(&self, _: Option<Duration>) -> io::Result<()> {
-        unimplemented!()
+        Ok(())
     }
 
     pub fn get_ref(&self) -> Self {
-        unimplemented!()
+        self.clone()
     }
 }
 
 impl Write for MockStream {
     fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+        if let Some(ref peer) = self.peer {
+            peer.lock().unwrap().write(msg)?;
+        }
         self.writer.lock().unwrap().write(msg)
     }
 
@@ -94,14 +130,51 @@ impl Write for MockStream {
 
 impl Read for MockStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.reader.lock().unwrap().read(buf)
+        let mut reader = self.reader.lock().unwrap();
+        if let Some(ref peer) = self.peer {
+            if reader.position() as usize >= reader.get_ref().len() {
+                let timeout = *self.read_timeout.lock().unwrap();
+                let data = peer.lock().unwrap().read(timeout)?;
+                reader.set_position(0);
+                *reader.get_mut() = data;
+            }
+        }
+        reader.read(buf)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::io::{Read, Write};
-    use super::MockStream;
+    use std::io::{self, Read, Write};
+    use std::time::Duration;
+    use super::{MockStream, MockPeer};
+
+    /// Echoes writes back, once per connection
+    struct EchoPeer {
+        written: Vec<u8>,
+        connections: usize
+    }
+
+    impl MockPeer for EchoPeer {
+        fn connect(&mut self) -> io::Result<()> {
+            self.connections += 1;
+            Ok(())
+        }
+
+        fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+            self.written.extend_from_slice(buf);
+            Ok(())
+        }
+
+        fn read(&mut self, timeout: Option<Duration>) -> io::Result<Vec<u8>> {
+            if self.written.is_empty() && timeout.is_some() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "nothing to echo"));
+            }
+            let mut data = ::std::mem::take(&mut self.written);
+            data.push(self.connections as u8);
+            Ok(data)
+        }
+    }
 
     #[test]
     fn write_take_test() {
@@ -136,4 +209,22 @@ mod test {
         mock.read_to_end(&mut vec).unwrap();
         assert_eq!(vec, vec![8,9,10]);
     }
+
+    #[test]
+    fn peer_test() {
+        let mock = MockStream::with_peer(EchoPeer { written: Vec::new(), connections: 0 });
+        let mut stream = mock.connect().unwrap();
+        stream.write_all(&[1, 2]).unwrap();
+        let mut buf = [0; 3];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 1]);
+
+        stream.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        assert_eq!(stream.read(&mut buf).unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+        let mut stream = mock.connect().unwrap();
+        stream.write_all(&[3]).unwrap();
+        stream.read_exact(&mut buf[..2]).unwrap();
+        assert_eq!(&buf[..2], &[3, 2]);
+    }
 }
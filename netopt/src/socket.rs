@@ -0,0 +1,292 @@
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use socket2::{Domain, Protocol, SockAddr, Socket, SockRef, TcpKeepalive, Type};
+
+/// TCP keepalive probing. `interval` and `retries` are left to the system
+/// on platforms that can't set them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// Idle time before the first probe
+    pub time: Duration,
+    /// Time between unanswered probes
+    pub interval: Option<Duration>,
+    /// Unanswered probes before the connection is dropped
+    pub retries: Option<u32>
+}
+
+impl Keepalive {
+    pub fn new(time: Duration) -> Keepalive {
+        Keepalive {
+            time: time,
+            interval: None,
+            retries: None
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios",
+              target_os = "freebsd", target_os = "netbsd"))]
+    fn to_socket2(self) -> TcpKeepalive {
+        let mut keepalive = TcpKeepalive::new().with_time(self.time);
+        if let Some(interval) = self.interval {
+            keepalive = keepalive.with_interval(interval);
+        }
+        if let Some(retries) = self.retries {
+            keepalive = keepalive.with_retries(retries);
+        }
+        keepalive
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios",
+                  target_os = "freebsd", target_os = "netbsd")))]
+    fn to_socket2(self) -> TcpKeepalive {
+        TcpKeepalive::new().with_time(self.time)
+    }
+}
+
+/// Delay before racing the next address while an attempt is still pending
+const ATTEMPT_DELAY: u64 = 250;
+
+/// How TCP connections are opened and set up.
+#[derive(Debug, Clone)]
+pub struct SocketOptions {
+    pub connect_timeout: Option<Duration>,
+    pub attempt_delay: Duration,
+    pub nodelay: Option<bool>,
+    pub keepalive: Option<Keepalive>,
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+    pub local_addr: Option<SocketAddr>
+}
+
+impl Default for SocketOptions {
+    fn default() -> SocketOptions {
+        SocketOptions {
+            connect_timeout: None,
+            attempt_delay: Duration::from_millis(ATTEMPT_DELAY),
+            nodelay: None,
+            keepalive: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            local_addr: None
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Tries every resolved address, alternating between IPv6 and IPv4 and
+    /// starting the next attempt when the last one fails or stays pending
+    /// for `attempt_delay`. The first connection made wins.
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<TcpStream> {
+        let deadline = self.connect_timeout.map(|timeout| Instant::now() + timeout);
+        let local = self.local_addr;
+        let addrs = interleave(addr.to_socket_addrs()?
+            .filter(|addr| local.is_none_or(|local| local.is_ipv6() == addr.is_ipv6()))
+            .collect());
+        if addrs.len() == 1 {
+            return self.connect_addr(addrs[0], self.connect_timeout);
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let mut next = 0;
+        let mut pending = 0;
+        let mut last_err = None;
+        loop {
+            if next < addrs.len() {
+                let timeout = remaining(deadline)?;
+                let (tx, options, addr) = (tx.clone(), self.clone(), addrs[next]);
+                // a losing connection is dropped once the receiver is gone
+                thread::spawn(move || tx.send(options.connect_addr(addr, timeout)));
+                next += 1;
+                pending += 1;
+            }
+            if pending == 0 {
+                return Err(last_err.unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to")
+                }));
+            }
+
+            let wait = if next < addrs.len() {
+                Some(self.attempt_delay)
+            } else {
+                remaining(deadline)?
+            };
+            let result = match wait {
+                Some(wait) => rx.recv_timeout(wait),
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            };
+            match result {
+                Ok(Ok(stream)) => return Ok(stream),
+                Ok(Err(err)) => {
+                    pending -= 1;
+                    last_err = Some(err);
+                },
+                Err(_) => ()
+            }
+        }
+    }
+
+    fn connect_addr(&self, addr: SocketAddr, timeout: Option<Duration>) -> io::Result<TcpStream> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        if let Some(local) = self.local_addr {
+            socket.bind(&local.into())?;
+        }
+        // buffer sizes have to be set before connecting to affect the window
+        self.apply(&socket)?;
+        let addr = SockAddr::from(addr);
+        match timeout {
+            Some(timeout) => socket.connect_timeout(&addr, timeout)?,
+            None => socket.connect(&addr)?
+        }
+        Ok(socket.into())
+    }
+
+    /// Sets up an accepted stream the same way as outgoing ones
+    pub fn apply_to(&self, stream: &TcpStream) -> io::Result<()> {
+        self.apply(&SockRef::from(stream))
+    }
+
+    fn apply(&self, socket: &Socket) -> io::Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay)?;
+        }
+        if let Some(keepalive) = self.keepalive {
+            socket.set_tcp_keepalive(&keepalive.to_socket2())?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        Ok(())
+    }
+}
+
+/// Time left until `deadline`, failing once it has passed
+fn remaining(deadline: Option<Instant>) -> io::Result<Option<Duration>> {
+    match deadline {
+        Some(deadline) => {
+            let now = Instant::now();
+            if now >= deadline {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))
+            } else {
+                Ok(Some(deadline - now))
+            }
+        },
+        None => Ok(None)
+    }
+}
+
+/// Alternates address families, starting with the family of the first address
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = addrs.first().is_some_and(|addr| addr.is_ipv6());
+    let (first, second): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|addr| addr.is_ipv6() == first_v6);
+    let mut interleaved = Vec::with_capacity(first.len() + second.len());
+    let (mut first, mut second) = (first.into_iter(), second.into_iter());
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return interleaved,
+            (a, b) => interleaved.extend(a.into_iter().chain(b))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::time::{Duration, Instant};
+    use socket2::{Domain, SockRef, Socket, Type};
+    use super::{SocketOptions, Keepalive, interleave};
+
+    /// Listener with a full backlog, so new connections hang in the handshake
+    fn saturated() -> Option<(Socket, SocketAddr, Vec<TcpStream>)> {
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        socket.bind(&"127.0.0.1:0".parse::<SocketAddr>().unwrap().into()).unwrap();
+        socket.listen(0).unwrap();
+        let addr = socket.local_addr().unwrap().as_socket().unwrap();
+        let mut streams = Vec::new();
+        for _ in 0..16 {
+            match TcpStream::connect_timeout(&addr, Duration::from_millis(100)) {
+                Ok(stream) => streams.push(stream),
+                Err(_) => return Some((socket, addr, streams))
+            }
+        }
+        // the platform doesn't drop connections beyond the backlog
+        None
+    }
+
+    #[test]
+    fn interleave_test() {
+        let addrs: Vec<SocketAddr> = ["[::1]:1", "[::2]:1", "[::3]:1", "10.0.0.1:1", "10.0.0.2:1"].iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        let interleaved = interleave(addrs.clone());
+        assert_eq!(interleaved, vec![addrs[0], addrs[3], addrs[1], addrs[4], addrs[2]]);
+    }
+
+    #[test]
+    fn fallback_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let mut addrs = vec![closed];
+        let saturated = saturated();
+        if let Some((_, addr, _)) = saturated {
+            addrs.push(addr);
+        }
+        addrs.push(listener.local_addr().unwrap());
+
+        let options = SocketOptions {
+            attempt_delay: Duration::from_millis(50),
+            connect_timeout: Some(Duration::from_secs(5)),
+            ..SocketOptions::default()
+        };
+        let stream = options.connect(&addrs[..]).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+    }
+
+    #[test]
+    fn connect_timeout_test() {
+        let (_socket, addr, _streams) = match saturated() {
+            Some(saturated) => saturated,
+            None => return
+        };
+        let options = SocketOptions {
+            connect_timeout: Some(Duration::from_millis(200)),
+            ..SocketOptions::default()
+        };
+        let start = Instant::now();
+        let err = options.connect(addr).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn socket_options_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut options = SocketOptions {
+            nodelay: Some(true),
+            keepalive: Some(Keepalive {
+                time: Duration::from_secs(30),
+                interval: Some(Duration::from_secs(5)),
+                retries: Some(3)
+            }),
+            send_buffer_size: Some(64 * 1024),
+            local_addr: Some("127.0.0.1:0".parse().unwrap()),
+            ..SocketOptions::default()
+        };
+
+        let stream = options.connect(listener.local_addr().unwrap()).unwrap();
+        let socket = SockRef::from(&stream);
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+
+        // only addresses of the local address family are tried
+        options.local_addr = Some("[::1]:0".parse().unwrap());
+        assert!(options.connect(listener.local_addr().unwrap()).is_err());
+    }
+}
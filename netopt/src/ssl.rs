@@ -1,32 +1,103 @@
-use std::net::TcpStream;
-use std::io;
+use std::net::{TcpStream, IpAddr};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
 use std::sync::Arc;
 use std::path::Path;
-use openssl::ssl::{self, SslFiletype, SslMethod, SslVerifyMode};
+use openssl::error::ErrorStack;
+use openssl::pkcs12::Pkcs12;
+use openssl::ssl::{self, SslFiletype, SslMethod, SslVerifyMode, SslVersion, AlpnError};
+use openssl::x509::X509;
+use tls::{SslContextBuilder, TlsVersion, VerifyMode, Identity};
 
 pub type SslStream = ssl::SslStream<TcpStream>;
-pub type SslError = ssl::Error;
+#[derive(Debug)]
+pub enum SslError {
+    Io(io::Error),
+    Ssl(ErrorStack)
+}
+
+impl fmt::Display for SslError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SslError::Io(ref err) => err.fmt(f),
+            SslError::Ssl(ref err) => err.fmt(f)
+        }
+    }
+}
+
+impl Error for SslError {}
+
+impl From<io::Error> for SslError {
+    fn from(err: io::Error) -> SslError {
+        SslError::Io(err)
+    }
+}
+
+impl From<ErrorStack> for SslError {
+    fn from(err: ErrorStack) -> SslError {
+        SslError::Ssl(err)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct SslContext {
-    inner: Arc<ssl::SslContext>
+    inner: Arc<ssl::SslContext>,
+    verify: bool,
+    alpn: Vec<u8>
 }
 
 impl Default for SslContext {
+    /// Client context trusting the system certificate store
     fn default() -> SslContext {
-        SslContext {
-            inner: Arc::new(ssl::SslContext::builder(SslMethod::tls()).unwrap().build())
-        }
+        let mut ctx = ssl::SslContext::builder(SslMethod::tls()).unwrap();
+        ctx.set_default_verify_paths().unwrap();
+        SslContext::new(ctx.build())
     }
 }
 
 impl SslContext {
     pub fn new(context: ssl::SslContext) -> Self {
         SslContext {
-            inner: Arc::new(context)
+            inner: Arc::new(context),
+            verify: true,
+            alpn: Vec::new()
         }
     }
 
+    /// Client context trusting the certificates in the `ca` PEM bundle
+    pub fn with_ca_file<A>(ca: A) -> Result<SslContext, SslError>
+    where A: AsRef<Path> {
+        let mut ctx = ssl::SslContext::builder(SslMethod::tls())?;
+        ctx.set_ca_file(ca.as_ref())?;
+        Ok(SslContext::new(ctx.build()))
+    }
+
+    /// Client context authenticating itself with `cert` and `key`
+    pub fn with_ca_file_and_client_cert<A, C, K>(ca: A, cert: C, key: K) -> Result<SslContext, SslError>
+    where A: AsRef<Path>, C: AsRef<Path>, K: AsRef<Path> {
+        let mut ctx = ssl::SslContext::builder(SslMethod::tls())?;
+        ctx.set_ca_file(ca.as_ref())?;
+        ctx.set_certificate_chain_file(cert.as_ref())?;
+        ctx.set_private_key_file(key.as_ref(), SslFiletype::PEM)?;
+        Ok(SslContext::new(ctx.build()))
+    }
+
+    /// Whether `connect` checks the server certificate chain and that it
+    /// was issued for the host being connected to. Enabled by default.
+    pub fn set_verify(&mut self, verify: bool) -> &mut SslContext {
+        self.verify = verify; self
+    }
+
+    /// Protocols offered by `connect` in order of preference. Servers choose
+    /// with the ALPN callback of the context given to `new`.
+    pub fn set_alpn_protocols(&mut self, protocols: &[&str]) -> &mut SslContext {
+        let protocols: Vec<String> = protocols.iter().map(|p| p.to_string()).collect();
+        self.alpn = alpn_wire(&protocols);
+        self
+    }
+
     pub fn with_cert_and_key<C, K>(cert: C, key: K) -> Result<SslContext, SslError>
     where C: AsRef<Path>, K: AsRef<Path> {
         let mut ctx = ssl::SslContext::builder(SslMethod::tls())?;
@@ -34,7 +105,7 @@ impl SslContext {
         ctx.set_certificate_file(cert.as_ref(), SslFiletype::PEM)?;
         ctx.set_private_key_file(key.as_ref(), SslFiletype::PEM)?;
         ctx.set_verify(SslVerifyMode::NONE);
-        Ok(SslContext { inner: Arc::new(ctx.build()) })
+        Ok(SslContext::new(ctx.build()))
     }
 
     pub fn with_cert_and_key_and_ca<C, K, A>(cert: C, key: K, ca: A) -> Result<SslContext, SslError>
@@ -45,7 +116,84 @@ impl SslContext {
         ctx.set_private_key_file(key.as_ref(), SslFiletype::PEM)?;
         ctx.set_ca_file(ca.as_ref())?;
         ctx.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
-        Ok(SslContext { inner: Arc::new(ctx.build()) })
+        Ok(SslContext::new(ctx.build()))
+    }
+
+    pub(crate) fn build_client(builder: &SslContextBuilder) -> Result<SslContext, SslError> {
+        let mut ctx = SslContext::configure(builder, SslMethod::tls_client())?;
+        if !builder.alpn.is_empty() {
+            ctx.set_alpn_protos(&alpn_wire(&builder.alpn))?;
+        }
+        let mut context = SslContext::new(ctx.build());
+        context.verify = builder.verify != VerifyMode::None;
+        Ok(context)
+    }
+
+    pub(crate) fn build_server(builder: &SslContextBuilder) -> Result<SslContext, SslError> {
+        let mut ctx = SslContext::configure(builder, SslMethod::tls_server())?;
+        ctx.set_verify(match builder.verify {
+            VerifyMode::None => SslVerifyMode::NONE,
+            VerifyMode::Peer => SslVerifyMode::PEER,
+            VerifyMode::PeerRequired => SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT
+        });
+        if !builder.alpn.is_empty() {
+            let protocols = builder.alpn.clone();
+            ctx.set_alpn_select_callback(move |_, client| {
+                select_alpn(&protocols, client).ok_or(AlpnError::NOACK)
+            });
+        }
+        Ok(SslContext::new(ctx.build()))
+    }
+
+    fn configure(builder: &SslContextBuilder, method: SslMethod) -> Result<ssl::SslContextBuilder, SslError> {
+        let mut ctx = ssl::SslContext::builder(method)?;
+        if builder.system_roots {
+            ctx.set_default_verify_paths()?;
+        }
+        for file in &builder.ca_files {
+            ctx.set_ca_file(file)?;
+        }
+        for dir in &builder.ca_dirs {
+            for cert in load_ca_dir(dir)? {
+                ctx.cert_store_mut().add_cert(cert)?;
+            }
+        }
+        match builder.identity {
+            Some(Identity::Pem { ref cert, ref key }) => {
+                ctx.set_certificate_chain_file(cert)?;
+                ctx.set_private_key_file(key, SslFiletype::PEM)?;
+                ctx.check_private_key()?;
+            },
+            Some(Identity::Pkcs12 { ref path, ref password }) => {
+                let der = read_file(path)?;
+                let identity = Pkcs12::from_der(&der)?.parse2(password)?;
+                if let Some(cert) = identity.cert {
+                    ctx.set_certificate(&cert)?;
+                }
+                if let Some(key) = identity.pkey {
+                    ctx.set_private_key(&key)?;
+                }
+                for cert in identity.ca.into_iter().flatten() {
+                    ctx.add_extra_chain_cert(cert)?;
+                }
+                ctx.check_private_key()?;
+            },
+            None => ()
+        }
+        ctx.set_min_proto_version(builder.min_version.map(ssl_version))?;
+        ctx.set_max_proto_version(builder.max_version.map(ssl_version))?;
+        if let Some(ref ciphers) = builder.ciphers {
+            let (tls13, older): (Vec<&str>, Vec<&str>) = ciphers.split(':')
+                .filter(|cipher| !cipher.is_empty())
+                .partition(|cipher| cipher.starts_with("TLS_"));
+            if !older.is_empty() {
+                ctx.set_cipher_list(&older.join(":"))?;
+            }
+            if !tls13.is_empty() {
+                ctx.set_ciphersuites(&tls13.join(":"))?;
+            }
+        }
+        Ok(ctx)
     }
 
     pub fn accept(&self, stream: TcpStream) -> Result<SslStream, io::Error> {
@@ -55,10 +203,161 @@ impl SslContext {
         }
     }
 
-    pub fn connect(&self, stream: TcpStream) -> Result<SslStream, io::Error> {
-        match ssl::Ssl::new(&*self.inner)?.connect(stream) {
+    /// Connects to the server named `domain`, which is sent with SNI and
+    /// matched against its certificate. IP addresses are matched as is.
+    pub fn connect(&self, stream: TcpStream, domain: &str) -> Result<SslStream, io::Error> {
+        let mut ssl = ssl::Ssl::new(&*self.inner)?;
+        let ip = domain.parse::<IpAddr>().ok();
+        if ip.is_none() {
+            ssl.set_hostname(domain)?;
+        }
+        if self.verify {
+            ssl.set_verify(SslVerifyMode::PEER);
+            match ip {
+                Some(ip) => ssl.param_mut().set_ip(ip)?,
+                None => ssl.param_mut().set_host(domain)?
+            }
+        } else {
+            ssl.set_verify(SslVerifyMode::NONE);
+        }
+        if !self.alpn.is_empty() {
+            ssl.set_alpn_protos(&self.alpn)?;
+        }
+
+        match ssl.connect(stream) {
             Ok(stream) => Ok(stream),
             Err(err) => Err(io::Error::new(io::ErrorKind::ConnectionAborted, err).into())
         }
     }
 }
+
+fn ssl_version(version: TlsVersion) -> SslVersion {
+    match version {
+        TlsVersion::Tls1 => SslVersion::TLS1,
+        TlsVersion::Tls1_1 => SslVersion::TLS1_1,
+        TlsVersion::Tls1_2 => SslVersion::TLS1_2,
+        TlsVersion::Tls1_3 => SslVersion::TLS1_3
+    }
+}
+
+fn alpn_wire(protocols: &[String]) -> Vec<u8> {
+    let mut wire = Vec::new();
+    for protocol in protocols {
+        wire.push(protocol.len() as u8);
+        wire.extend_from_slice(protocol.as_bytes());
+    }
+    wire
+}
+
+/// First of our `protocols` offered in the `client` wire format list
+fn select_alpn<'a>(protocols: &[String], client: &'a [u8]) -> Option<&'a [u8]> {
+    let mut offered = Vec::new();
+    let mut rest = client;
+    while let Some((&len, tail)) = rest.split_first() {
+        if tail.len() < len as usize {
+            break;
+        }
+        let (protocol, tail) = tail.split_at(len as usize);
+        offered.push(protocol);
+        rest = tail;
+    }
+    protocols.iter().filter_map(|p| offered.iter().find(|o| **o == p.as_bytes()).cloned()).next()
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, SslError> {
+    let mut data = Vec::new();
+    fs::File::open(path).and_then(|mut f| f.read_to_end(&mut data))?;
+    Ok(data)
+}
+
+/// Certificates of every PEM file in `dir`, files without any are skipped
+fn load_ca_dir(dir: &Path) -> Result<Vec<X509>, SslError> {
+    let mut certs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            if let Ok(found) = X509::stack_from_pem(&read_file(&path)?) {
+                certs.extend(found);
+            }
+        }
+    }
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::ssl::{self, SslMethod};
+    use openssl::x509::{X509, X509NameBuilder};
+    use openssl::x509::extension::SubjectAlternativeName;
+    use super::SslContext;
+
+    fn self_signed() -> (X509, PKey<Private>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        let san = SubjectAlternativeName::new().dns("localhost").ip("127.0.0.1")
+            .build(&cert.x509v3_context(None, None)).unwrap();
+        cert.append_extension(san).unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+        (cert.build(), key)
+    }
+
+    fn handshake(domain: &str, verify: bool) -> bool {
+        let (cert, key) = self_signed();
+        let mut server = ssl::SslContext::builder(SslMethod::tls()).unwrap();
+        server.set_certificate(&cert).unwrap();
+        server.set_private_key(&key).unwrap();
+        let server = SslContext::new(server.build());
+
+        let mut client = ssl::SslContext::builder(SslMethod::tls()).unwrap();
+        client.cert_store_mut().add_cert(cert).unwrap();
+        let mut client = SslContext::new(client.build());
+        client.set_verify(verify);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            if let Ok(mut stream) = server.accept(stream) {
+                let mut buf = [0; 1];
+                let _ = stream.read_exact(&mut buf).and_then(|_| stream.write_all(&buf));
+            }
+        });
+
+        let stream = ::std::net::TcpStream::connect(addr).unwrap();
+        match client.connect(stream, domain) {
+            Ok(mut stream) => {
+                let mut buf = [0; 1];
+                stream.write_all(&[7]).unwrap();
+                stream.read_exact(&mut buf).unwrap();
+                buf == [7]
+            },
+            Err(_) => false
+        }
+    }
+
+    #[test]
+    fn ssl_verify_hostname_test() {
+        assert!(handshake("localhost", true));
+        assert!(handshake("127.0.0.1", true));
+        assert!(!handshake("example.com", true));
+        assert!(!handshake("127.0.0.2", true));
+        assert!(handshake("example.com", false));
+    }
+}
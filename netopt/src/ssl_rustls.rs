@@ -0,0 +1,488 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+use rustls::{self, ClientConfig, ServerConfig, ClientConnection, ServerConnection, RootCertStore, StreamOwned};
+use rustls::{DigitallySignedStruct, SignatureScheme, SupportedProtocolVersion};
+use rustls::client::danger::{ServerCertVerifier, ServerCertVerified, HandshakeSignatureValid};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::pki_types::pem::{self, PemObject};
+use rustls::server::{VerifierBuilderError, WebPkiClientVerifier};
+use rustls_native_certs;
+use webpki_roots;
+use tls::{SslContextBuilder, TlsVersion, VerifyMode, Identity};
+
+#[derive(Debug)]
+pub enum SslError {
+    Io(io::Error),
+    Pem(pem::Error),
+    Tls(rustls::Error),
+    Verifier(VerifierBuilderError),
+    NoCertificates,
+    /// Settings rustls can't provide, such as PKCS#12 or TLS 1.1
+    Unsupported(&'static str)
+}
+
+impl fmt::Display for SslError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SslError::Io(ref err) => err.fmt(f),
+            SslError::Pem(ref err) => err.fmt(f),
+            SslError::Tls(ref err) => err.fmt(f),
+            SslError::Verifier(ref err) => err.fmt(f),
+            SslError::NoCertificates => write!(f, "no certificates found"),
+            SslError::Unsupported(what) => write!(f, "{} isn't supported by rustls", what)
+        }
+    }
+}
+
+impl Error for SslError {}
+
+impl From<io::Error> for SslError {
+    fn from(err: io::Error) -> SslError {
+        SslError::Io(err)
+    }
+}
+
+impl From<pem::Error> for SslError {
+    fn from(err: pem::Error) -> SslError {
+        SslError::Pem(err)
+    }
+}
+
+impl From<rustls::Error> for SslError {
+    fn from(err: rustls::Error) -> SslError {
+        SslError::Tls(err)
+    }
+}
+
+impl From<VerifierBuilderError> for SslError {
+    fn from(err: VerifierBuilderError) -> SslError {
+        SslError::Verifier(err)
+    }
+}
+
+pub enum SslStream {
+    Client(StreamOwned<ClientConnection, TcpStream>),
+    Server(StreamOwned<ServerConnection, TcpStream>)
+}
+
+impl SslStream {
+    pub fn get_ref(&self) -> &TcpStream {
+        match *self {
+            SslStream::Client(ref s) => s.get_ref(),
+            SslStream::Server(ref s) => s.get_ref()
+        }
+    }
+
+    /// Protocol agreed on with ALPN
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        match *self {
+            SslStream::Client(ref s) => s.conn.alpn_protocol(),
+            SslStream::Server(ref s) => s.conn.alpn_protocol()
+        }
+    }
+}
+
+impl Read for SslStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            SslStream::Client(ref mut s) => s.read(buf),
+            SslStream::Server(ref mut s) => s.read(buf)
+        }
+    }
+}
+
+impl Write for SslStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            SslStream::Client(ref mut s) => s.write(buf),
+            SslStream::Server(ref mut s) => s.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            SslStream::Client(ref mut s) => s.flush(),
+            SslStream::Server(ref mut s) => s.flush()
+        }
+    }
+}
+
+/// TLS configuration backed by rustls.
+///
+/// A context built for the client side can only `connect` and one built for
+/// the server side can only `accept`.
+#[derive(Debug, Clone)]
+pub struct SslContext {
+    client: Option<Arc<ClientConfig>>,
+    server: Option<Arc<ServerConfig>>,
+    verify: bool
+}
+
+impl Default for SslContext {
+    /// Client context trusting the Mozilla root certificates
+    fn default() -> SslContext {
+        let roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+        let config = client_builder().unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        SslContext::from_client_config(config)
+    }
+}
+
+impl SslContext {
+    pub fn from_client_config(config: ClientConfig) -> Self {
+        SslContext {
+            client: Some(Arc::new(config)),
+            server: None,
+            verify: true
+        }
+    }
+
+    pub fn from_server_config(config: ServerConfig) -> Self {
+        SslContext {
+            client: None,
+            server: Some(Arc::new(config)),
+            verify: true
+        }
+    }
+
+    /// Client context trusting the certificates in the `ca` PEM bundle
+    pub fn with_ca_file<A>(ca: A) -> Result<SslContext, SslError>
+    where A: AsRef<Path> {
+        let config = client_builder()?
+            .with_root_certificates(load_roots(ca.as_ref())?)
+            .with_no_client_auth();
+        Ok(SslContext::from_client_config(config))
+    }
+
+    /// Client context authenticating itself with `cert` and `key`
+    pub fn with_ca_file_and_client_cert<A, C, K>(ca: A, cert: C, key: K) -> Result<SslContext, SslError>
+    where A: AsRef<Path>, C: AsRef<Path>, K: AsRef<Path> {
+        let config = client_builder()?
+            .with_root_certificates(load_roots(ca.as_ref())?)
+            .with_client_auth_cert(load_certs(cert.as_ref())?, load_key(key.as_ref())?)?;
+        Ok(SslContext::from_client_config(config))
+    }
+
+    pub fn with_cert_and_key<C, K>(cert: C, key: K) -> Result<SslContext, SslError>
+    where C: AsRef<Path>, K: AsRef<Path> {
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(load_certs(cert.as_ref())?, load_key(key.as_ref())?)?;
+        Ok(SslContext::from_server_config(config))
+    }
+
+    /// Server context requiring clients to present a certificate signed by `ca`
+    pub fn with_cert_and_key_and_ca<C, K, A>(cert: C, key: K, ca: A) -> Result<SslContext, SslError>
+    where C: AsRef<Path>, K: AsRef<Path>, A: AsRef<Path> {
+        let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(load_roots(ca.as_ref())?), provider())
+            .build()?;
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(load_certs(cert.as_ref())?, load_key(key.as_ref())?)?;
+        Ok(SslContext::from_server_config(config))
+    }
+
+    /// Whether `connect` checks the server certificate chain and that it
+    /// was issued for the host being connected to. Enabled by default.
+    pub fn set_verify(&mut self, verify: bool) -> &mut SslContext {
+        self.verify = verify; self
+    }
+
+    /// Protocols offered, or accepted on the server side, in order of preference
+    pub fn set_alpn_protocols(&mut self, protocols: &[&str]) -> &mut SslContext {
+        let protocols: Vec<Vec<u8>> = protocols.iter().map(|p| p.as_bytes().to_vec()).collect();
+        if let Some(ref mut client) = self.client {
+            Arc::make_mut(client).alpn_protocols = protocols.clone();
+        }
+        if let Some(ref mut server) = self.server {
+            Arc::make_mut(server).alpn_protocols = protocols;
+        }
+        self
+    }
+
+    pub(crate) fn build_client(builder: &SslContextBuilder) -> Result<SslContext, SslError> {
+        let config = ClientConfig::builder_with_provider(configure_provider(builder)?)
+            .with_protocol_versions(&protocol_versions(builder)?)?
+            .with_root_certificates(load_builder_roots(builder)?);
+        let mut config = match builder.identity {
+            Some(Identity::Pem { ref cert, ref key }) => config.with_client_auth_cert(load_certs(cert)?, load_key(key)?)?,
+            Some(Identity::Pkcs12 { .. }) => return Err(SslError::Unsupported("PKCS#12")),
+            None => config.with_no_client_auth()
+        };
+        config.alpn_protocols = builder.alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+        let mut context = SslContext::from_client_config(config);
+        context.verify = builder.verify != VerifyMode::None;
+        Ok(context)
+    }
+
+    pub(crate) fn build_server(builder: &SslContextBuilder) -> Result<SslContext, SslError> {
+        let provider = configure_provider(builder)?;
+        let roots = load_builder_roots(builder)?;
+        let config = ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&protocol_versions(builder)?)?;
+        let config = match builder.verify {
+            VerifyMode::Peer if !roots.is_empty() => {
+                config.with_client_cert_verifier(WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                    .allow_unauthenticated()
+                    .build()?)
+            },
+            VerifyMode::PeerRequired => {
+                config.with_client_cert_verifier(WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                    .build()?)
+            },
+            _ => config.with_no_client_auth()
+        };
+        let mut config = match builder.identity {
+            Some(Identity::Pem { ref cert, ref key }) => config.with_single_cert(load_certs(cert)?, load_key(key)?)?,
+            Some(Identity::Pkcs12 { .. }) => return Err(SslError::Unsupported("PKCS#12")),
+            None => return Err(SslError::NoCertificates)
+        };
+        config.alpn_protocols = builder.alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+        Ok(SslContext::from_server_config(config))
+    }
+
+    pub fn accept(&self, stream: TcpStream) -> Result<SslStream, io::Error> {
+        let config = match self.server {
+            Some(ref config) => config.clone(),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "TLS context has no server configuration"))
+        };
+        let conn = ServerConnection::new(config).map_err(aborted)?;
+        let mut stream = StreamOwned::new(conn, stream);
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock).map_err(aborted)?;
+        }
+        Ok(SslStream::Server(stream))
+    }
+
+    /// Connects to the server named `domain`, which is sent with SNI and
+    /// matched against its certificate. IP addresses are matched as is.
+    pub fn connect(&self, stream: TcpStream, domain: &str) -> Result<SslStream, io::Error> {
+        let mut config = match self.client {
+            Some(ref config) => config.clone(),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "TLS context has no client configuration"))
+        };
+        if !self.verify {
+            let mut unverified = (*config).clone();
+            unverified.dangerous().set_certificate_verifier(Arc::new(NoVerifier(provider())));
+            config = Arc::new(unverified);
+        }
+        let name = ServerName::try_from(domain.to_owned())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let conn = ClientConnection::new(config, name).map_err(aborted)?;
+        let mut stream = StreamOwned::new(conn, stream);
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock).map_err(aborted)?;
+        }
+        Ok(SslStream::Client(stream))
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+fn configure_provider(builder: &SslContextBuilder) -> Result<Arc<CryptoProvider>, SslError> {
+    let mut provider = crypto::ring::default_provider();
+    if let Some(ref ciphers) = builder.ciphers {
+        let names: Vec<&str> = ciphers.split(':').filter(|name| !name.is_empty()).collect();
+        provider.cipher_suites.retain(|suite| {
+            suite.suite().as_str().is_some_and(|name| names.contains(&name))
+        });
+        if provider.cipher_suites.is_empty() {
+            return Err(SslError::Unsupported("the cipher suite list"));
+        }
+    }
+    Ok(Arc::new(provider))
+}
+
+fn protocol_versions(builder: &SslContextBuilder) -> Result<Vec<&'static SupportedProtocolVersion>, SslError> {
+    let min = builder.min_version.unwrap_or(TlsVersion::Tls1_2);
+    let max = builder.max_version.unwrap_or(TlsVersion::Tls1_3);
+    let versions: Vec<&'static SupportedProtocolVersion> = [(TlsVersion::Tls1_2, &rustls::version::TLS12), (TlsVersion::Tls1_3, &rustls::version::TLS13)]
+        .iter()
+        .filter(|&&(version, _)| min <= version && version <= max)
+        .map(|&(_, supported)| supported)
+        .collect();
+    if versions.is_empty() {
+        return Err(SslError::Unsupported("TLS versions below 1.2"));
+    }
+    Ok(versions)
+}
+
+fn load_builder_roots(builder: &SslContextBuilder) -> Result<RootCertStore, SslError> {
+    let mut roots = RootCertStore::empty();
+    if builder.system_roots {
+        for cert in rustls_native_certs::load_native_certs().certs {
+            // the system store may hold certificates webpki can't parse
+            let _ = roots.add(cert);
+        }
+    }
+    for file in &builder.ca_files {
+        for cert in load_certs(file)? {
+            roots.add(cert)?;
+        }
+    }
+    for dir in &builder.ca_dirs {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            // files without certificates, like keys, are skipped
+            for cert in CertificateDer::pem_file_iter(&path)?.filter_map(|cert| cert.ok()) {
+                roots.add(cert)?;
+            }
+        }
+    }
+    Ok(roots)
+}
+
+fn client_builder() -> Result<rustls::ConfigBuilder<ClientConfig, rustls::WantsVerifier>, SslError> {
+    Ok(ClientConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?)
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, SslError> {
+    let certs = CertificateDer::pem_file_iter(path)?.collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(SslError::NoCertificates);
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, SslError> {
+    Ok(PrivateKeyDer::from_pem_file(path)?)
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore, SslError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
+
+fn aborted<E: Into<Box<dyn Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, err)
+}
+
+/// Accepts any server certificate, used when verification is turned off
+#[derive(Debug)]
+struct NoVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(&self,
+                          _: &CertificateDer,
+                          _: &[CertificateDer],
+                          _: &ServerName,
+                          _: &[u8],
+                          _: UnixTime)
+                          -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self,
+                              message: &[u8],
+                              cert: &CertificateDer,
+                              dss: &DigitallySignedStruct)
+                              -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self,
+                              message: &[u8],
+                              cert: &CertificateDer,
+                              dss: &DigitallySignedStruct)
+                              -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use super::SslContext;
+
+    fn cert(name: &str) -> String {
+        format!("{}/tests/certs/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn echo(server: SslContext, client: &SslContext, domain: &str) -> Option<Option<Vec<u8>>> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            if let Ok(mut stream) = server.accept(stream) {
+                let mut buf = [0; 1];
+                let _ = stream.read_exact(&mut buf).and_then(|_| stream.write_all(&buf));
+            }
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut stream = match client.connect(stream, domain) {
+            Ok(stream) => stream,
+            Err(_) => return None
+        };
+        // TLS 1.3 clients learn that their certificate was rejected on the first read
+        let mut buf = [0; 1];
+        stream.write_all(&[7]).and_then(|_| stream.read_exact(&mut buf)).ok()?;
+        assert_eq!(buf, [7]);
+        Some(stream.alpn_protocol().map(|p| p.to_vec()))
+    }
+
+    fn server() -> SslContext {
+        SslContext::with_cert_and_key(cert("server.pem"), cert("server.key")).unwrap()
+    }
+
+    #[test]
+    fn rustls_verify_hostname_test() {
+        let mut client = SslContext::with_ca_file(cert("ca.pem")).unwrap();
+        assert!(echo(server(), &client, "localhost").is_some());
+        assert!(echo(server(), &client, "127.0.0.1").is_some());
+        assert!(echo(server(), &client, "example.com").is_none());
+        assert!(echo(server(), &SslContext::default(), "localhost").is_none());
+
+        client.set_verify(false);
+        assert!(echo(server(), &client, "example.com").is_some());
+    }
+
+    #[test]
+    fn rustls_client_cert_test() {
+        let server = || SslContext::with_cert_and_key_and_ca(cert("server.pem"), cert("server.key"), cert("ca.pem")).unwrap();
+        let client = SslContext::with_ca_file_and_client_cert(cert("ca.pem"), cert("client.pem"), cert("client.key")).unwrap();
+        assert!(echo(server(), &client, "localhost").is_some());
+
+        let anonymous = SslContext::with_ca_file(cert("ca.pem")).unwrap();
+        assert!(echo(server(), &anonymous, "localhost").is_none());
+    }
+
+    #[test]
+    fn rustls_alpn_test() {
+        let mut server = server();
+        server.set_alpn_protocols(&["mqtt"]);
+        let mut client = SslContext::with_ca_file(cert("ca.pem")).unwrap();
+        client.set_alpn_protocols(&["x-amzn-mqtt-ca", "mqtt"]);
+        assert_eq!(echo(server, &client, "localhost"), Some(Some(b"mqtt".to_vec())));
+    }
+
+    #[test]
+    fn rustls_missing_file_test() {
+        assert!(SslContext::with_ca_file(cert("missing.pem")).is_err());
+        assert!(SslContext::with_cert_and_key(cert("server.key"), cert("server.key")).is_err());
+    }
+}
@@ -1,18 +1,145 @@
-use std::net::{TcpListener, TcpStream, SocketAddr, ToSocketAddrs, Shutdown, SocketAddrV4, Ipv4Addr};
+use std::net::{TcpListener, TcpStream, SocketAddr, ToSocketAddrs, Shutdown, SocketAddrV4, SocketAddrV6, Ipv4Addr, IpAddr};
 use std::io::{self, Read, Write, BufReader, BufWriter};
 use std::time::Duration;
+use std::sync::{Arc, RwLock};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
 
 use ssl::{SslContext, SslStream};
+#[cfg(any(feature = "ssl", feature = "rustls"))]
+use tls::TlsHandle;
 use mock::MockStream;
+use fault::{FaultOptions, FaultStream};
+use proxy::Proxy;
+use socket::{SocketOptions, Keepalive};
+#[cfg(feature = "websocket")]
+use ws::{WsOptions, WsStream};
+#[cfg(unix)]
+use unix::{self, PeerCred};
 
 use NetworkStream::{
     Tcp,
     Ssl,
+    Faulty,
     Mock
 };
+#[cfg(feature = "websocket")]
+use NetworkStream::Ws;
+#[cfg(unix)]
+use NetworkStream::Unix;
+
+/// Socket addresses that remember the host name they were given by, which TLS
+/// sends with SNI and checks the server certificate against. A proxy is
+/// asked for the host and port as they are, without resolving them locally.
+pub trait ToHostAddrs: ToSocketAddrs {
+    fn host(&self) -> Option<String>;
+    fn port(&self) -> Option<u16>;
+}
+
+impl ToHostAddrs for str {
+    /// `"example.com:8883"` or `"[::1]:8883"`
+    fn host(&self) -> Option<String> {
+        let mut parts = self.rsplitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(_), Some(host)) => Some(host.trim_start_matches('[').trim_end_matches(']').to_owned()),
+            _ => None
+        }
+    }
+
+    fn port(&self) -> Option<u16> {
+        self.rsplitn(2, ':').next().and_then(|port| port.parse().ok())
+    }
+}
+
+impl ToHostAddrs for String {
+    fn host(&self) -> Option<String> {
+        self.as_str().host()
+    }
+
+    fn port(&self) -> Option<u16> {
+        self.as_str().port()
+    }
+}
+
+impl<'a> ToHostAddrs for (&'a str, u16) {
+    fn host(&self) -> Option<String> {
+        Some(self.0.to_owned())
+    }
+
+    fn port(&self) -> Option<u16> {
+        Some(self.1)
+    }
+}
+
+impl ToHostAddrs for (String, u16) {
+    fn host(&self) -> Option<String> {
+        Some(self.0.clone())
+    }
+
+    fn port(&self) -> Option<u16> {
+        Some(self.1)
+    }
+}
+
+impl ToHostAddrs for (IpAddr, u16) {
+    fn host(&self) -> Option<String> {
+        Some(self.0.to_string())
+    }
+
+    fn port(&self) -> Option<u16> {
+        Some(self.1)
+    }
+}
+
+impl ToHostAddrs for SocketAddr {
+    fn host(&self) -> Option<String> {
+        Some(self.ip().to_string())
+    }
+
+    fn port(&self) -> Option<u16> {
+        Some(self.port())
+    }
+}
+
+impl ToHostAddrs for SocketAddrV4 {
+    fn host(&self) -> Option<String> {
+        Some(self.ip().to_string())
+    }
+
+    fn port(&self) -> Option<u16> {
+        Some(self.port())
+    }
+}
+
+impl ToHostAddrs for SocketAddrV6 {
+    fn host(&self) -> Option<String> {
+        Some(self.ip().to_string())
+    }
+
+    fn port(&self) -> Option<u16> {
+        Some(self.port())
+    }
+}
+
+impl<'a, T: ToHostAddrs + ?Sized> ToHostAddrs for &'a T {
+    fn host(&self) -> Option<String> {
+        (**self).host()
+    }
+
+    fn port(&self) -> Option<u16> {
+        (**self).port()
+    }
+}
 
 pub struct NetworkOptions {
     ssl: Option<SslContext>,
+    #[cfg(feature = "websocket")]
+    ws: Option<WsOptions>,
+    proxy: Option<Proxy>,
+    socket: SocketOptions,
+    faults: Option<FaultOptions>,
     mock: Option<MockStream>
 }
 
@@ -20,6 +147,11 @@ impl NetworkOptions {
     pub fn new() -> NetworkOptions {
         NetworkOptions {
             ssl: None::<SslContext>,
+            #[cfg(feature = "websocket")]
+            ws: None::<WsOptions>,
+            proxy: None::<Proxy>,
+            socket: SocketOptions::default(),
+            faults: None::<FaultOptions>,
             mock: None::<MockStream>
         }
     }
@@ -32,52 +164,244 @@ impl NetworkOptions {
         self.ssl = Some(ssl); self
     }
 
+    pub fn has_tls(&self) -> bool {
+        self.ssl.is_some()
+    }
+
+    /// Speaks MQTT over WebSocket, on top of TLS when it's set as well
+    #[cfg(feature = "websocket")]
+    pub fn websocket(&mut self, ws: WsOptions) -> &mut NetworkOptions {
+        self.ws = Some(ws); self
+    }
+
+    /// Tunnels outgoing TCP connections, and TLS on top of them, through `proxy`
+    pub fn proxy(&mut self, proxy: Proxy) -> &mut NetworkOptions {
+        self.proxy = Some(proxy); self
+    }
+
+    /// Injects faults into outgoing connections, above TLS and WebSocket
+    pub fn faults(&mut self, faults: FaultOptions) -> &mut NetworkOptions {
+        self.faults = Some(faults); self
+    }
+
+    /// Gives up connecting, across all addresses, after `timeout`
+    pub fn connect_timeout(&mut self, timeout: Duration) -> &mut NetworkOptions {
+        self.socket.connect_timeout = Some(timeout); self
+    }
+
+    /// How long an attempt may stay pending before the next resolved
+    /// address is raced against it, 250ms by default
+    pub fn attempt_delay(&mut self, delay: Duration) -> &mut NetworkOptions {
+        self.socket.attempt_delay = delay; self
+    }
+
+    /// Sets `TCP_NODELAY`
+    pub fn nodelay(&mut self, nodelay: bool) -> &mut NetworkOptions {
+        self.socket.nodelay = Some(nodelay); self
+    }
+
+    /// Turns on `SO_KEEPALIVE`
+    pub fn keepalive(&mut self, keepalive: Keepalive) -> &mut NetworkOptions {
+        self.socket.keepalive = Some(keepalive); self
+    }
+
+    /// Sets `SO_SNDBUF`
+    pub fn send_buffer_size(&mut self, size: usize) -> &mut NetworkOptions {
+        self.socket.send_buffer_size = Some(size); self
+    }
+
+    /// Sets `SO_RCVBUF`
+    pub fn recv_buffer_size(&mut self, size: usize) -> &mut NetworkOptions {
+        self.socket.recv_buffer_size = Some(size); self
+    }
+
+    /// Binds outgoing connections to a local address, only addresses of
+    /// the same family are connected to then
+    pub fn local_addr(&mut self, addr: SocketAddr) -> &mut NetworkOptions {
+        self.socket.local_addr = Some(addr); self
+    }
+
     pub fn bind<A: ToSocketAddrs>(&self, addr: A) -> io::Result<NetworkListener> {
         Ok(NetworkListener {
-            tcp: TcpListener::bind(addr)?,
-            ssl: match self.ssl {
-                Some(ref ssl) => Some(ssl.clone()),
-                None => None
+            socket: ListenerSocket::Tcp(TcpListener::bind(addr)?),
+            options: self.socket.clone(),
+            ssl: self.ssl.clone().map(|ssl| Arc::new(RwLock::new(ssl))),
+            #[cfg(feature = "websocket")]
+            ws: self.ws.is_some()
+        })
+    }
+
+    pub fn connect<A: ToHostAddrs>(&self, addr: A) -> io::Result<NetworkStream> {
+        let host = addr.host();
+        match (self.proxy.as_ref(), host.as_ref(), addr.port()) {
+            // leave resolving the host name to the proxy
+            (Some(proxy), Some(host), Some(port)) if self.mock.is_none() => {
+                let mut stream = self.socket.connect(proxy.addr())?;
+                proxy.tunnel(&mut stream, host, port)?;
+                self.secure(stream, host)
+            },
+            _ => self.connect_host(addr, host.as_deref())
+        }
+    }
+
+    /// Connects to `addr`, naming the server `host` for TLS. The peer's IP
+    /// address is used when `host` isn't known.
+    pub fn connect_host<A: ToSocketAddrs>(&self, addr: A, host: Option<&str>) -> io::Result<NetworkStream> {
+        if let Some(ref mock) = self.mock {
+            return Ok(self.inject(NetworkStream::Mock(mock.connect()?)));
+        };
+
+        let (stream, ip) = match self.proxy {
+            Some(ref proxy) => {
+                let addr = addr.to_socket_addrs()?.next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to"))?;
+                let mut stream = self.socket.connect(proxy.addr())?;
+                proxy.tunnel(&mut stream, &addr.ip().to_string(), addr.port())?;
+                (stream, addr.ip())
+            },
+            None => {
+                let stream = self.socket.connect(addr)?;
+                let ip = stream.peer_addr()?.ip();
+                (stream, ip)
             }
+        };
+        match host {
+            Some(host) => self.secure(stream, host),
+            None => self.secure(stream, &ip.to_string())
+        }
+    }
+
+    fn secure(&self, stream: TcpStream, host: &str) -> io::Result<NetworkStream> {
+        let stream = match self.ssl {
+            Some(ref ssl) => NetworkStream::Ssl(ssl.connect(stream, host)?),
+            None => NetworkStream::Tcp(stream)
+        };
+        Ok(self.inject(self.upgrade(stream)?))
+    }
+
+    /// Listens on a unix socket at `path`, TLS isn't applied to it
+    #[cfg(unix)]
+    pub fn bind_unix<P: AsRef<Path>>(&self, path: P) -> io::Result<NetworkListener> {
+        Ok(NetworkListener {
+            socket: ListenerSocket::Unix(UnixListener::bind(path)?),
+            options: self.socket.clone(),
+            ssl: None,
+            #[cfg(feature = "websocket")]
+            ws: self.ws.is_some()
         })
     }
 
-    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<NetworkStream> {
+    /// Connects to a unix socket at `path`, TLS isn't applied to it
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(&self, path: P) -> io::Result<NetworkStream> {
         if let Some(ref mock) = self.mock {
-            return Ok(NetworkStream::Mock(mock.clone()));
+            return Ok(self.inject(NetworkStream::Mock(mock.connect()?)));
         };
 
-        let stream = TcpStream::connect(addr)?;
-        match self.ssl {
-            Some(ref ssl) => Ok(NetworkStream::Ssl(ssl.connect(stream)?)),
-            None => Ok(NetworkStream::Tcp(stream))
+        let stream = UnixStream::connect(path)?;
+        Ok(self.inject(self.upgrade(NetworkStream::Unix(stream))?))
+    }
+
+    fn inject(&self, stream: NetworkStream) -> NetworkStream {
+        match self.faults {
+            Some(ref faults) => NetworkStream::Faulty(Box::new(faults.wrap(stream))),
+            None => stream
         }
     }
+
+    #[cfg(feature = "websocket")]
+    fn upgrade(&self, stream: NetworkStream) -> io::Result<NetworkStream> {
+        match self.ws {
+            Some(ref ws) => Ok(NetworkStream::Ws(Box::new(WsStream::connect(stream, ws)?))),
+            None => Ok(stream)
+        }
+    }
+
+    #[cfg(not(feature = "websocket"))]
+    fn upgrade(&self, stream: NetworkStream) -> io::Result<NetworkStream> {
+        Ok(stream)
+    }
+}
+
+enum ListenerSocket {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener)
 }
 
 pub struct NetworkListener {
-    tcp: TcpListener,
-    ssl: Option<SslContext>,
+    socket: ListenerSocket,
+    options: SocketOptions,
+    ssl: Option<Arc<RwLock<SslContext>>>,
+    #[cfg(feature = "websocket")]
+    ws: bool
 }
 
 impl NetworkListener {
+    /// Unix socket peers have no inet address and are reported as `0.0.0.0:0`,
+    /// use `NetworkStream::peer_cred` to identify them.
     pub fn accept(&mut self) -> io::Result<(NetworkStream, SocketAddr)> {
-        let (stream, addr) = self.tcp.accept()?;
-        match self.ssl {
-            Some(ref ssl) => {
-                match ssl.accept(stream) {
-                    Ok(ssl_stream) => Ok((NetworkStream::Ssl(ssl_stream), addr)),
-                    Err(e) => Err(e)
-                }
+        match self.socket {
+            ListenerSocket::Tcp(ref tcp) => {
+                let (stream, addr) = tcp.accept()?;
+                self.options.apply_to(&stream)?;
+                let stream = match self.ssl {
+                    // `SslContext` is uninhabited without a TLS backend
+                    #[cfg_attr(not(any(feature = "ssl", feature = "rustls")), allow(unreachable_code, unused_variables))]
+                    Some(ref ssl) => {
+                        let ssl = ssl.read().unwrap().clone();
+                        NetworkStream::Ssl(ssl.accept(stream)?)
+                    },
+                    None => NetworkStream::Tcp(stream)
+                };
+                Ok((self.upgrade(stream)?, addr))
             },
-            None => Ok((NetworkStream::Tcp(stream), addr))
+            #[cfg(unix)]
+            ListenerSocket::Unix(ref unix) => {
+                let (stream, _) = unix.accept()?;
+                let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0,0,0,0), 0));
+                Ok((self.upgrade(NetworkStream::Unix(stream))?, addr))
+            }
+        }
+    }
+
+    /// Handle to replace the TLS context of a listener bound with one
+    #[cfg(any(feature = "ssl", feature = "rustls"))]
+    pub fn tls_handle(&self) -> Option<TlsHandle> {
+        self.ssl.as_ref().map(|ssl| TlsHandle::new(ssl.clone()))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self.socket {
+            ListenerSocket::Tcp(ref tcp) => tcp.local_addr(),
+            #[cfg(unix)]
+            ListenerSocket::Unix(_) => Err(no_inet_addr())
+        }
+    }
+
+    #[cfg(feature = "websocket")]
+    fn upgrade(&self, stream: NetworkStream) -> io::Result<NetworkStream> {
+        if self.ws {
+            Ok(NetworkStream::Ws(Box::new(WsStream::accept(stream)?)))
+        } else {
+            Ok(stream)
         }
     }
+
+    #[cfg(not(feature = "websocket"))]
+    fn upgrade(&self, stream: NetworkStream) -> io::Result<NetworkStream> {
+        Ok(stream)
+    }
 }
 
 pub enum NetworkStream {
     Tcp(TcpStream),
     Ssl(SslStream),
+    #[cfg(feature = "websocket")]
+    Ws(Box<WsStream>),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    Faulty(Box<FaultStream>),
     Mock(MockStream)
 }
 
@@ -86,7 +410,24 @@ impl NetworkStream {
         match *self {
             Tcp(ref s) => s.peer_addr(),
             Ssl(ref s) => s.get_ref().peer_addr(),
-            Mock(_) => Ok(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127,0,0,1), 80)))
+            #[cfg(feature = "websocket")]
+            Ws(ref s) => s.get_ref().peer_addr(),
+            #[cfg(unix)]
+            Unix(_) => Err(no_inet_addr()),
+            Faulty(ref s) => s.get_ref().peer_addr(),
+            Mock(ref s) => s.peer_addr()
+        }
+    }
+
+    /// Credentials of the peer process, available for unix sockets only
+    #[cfg(unix)]
+    pub fn peer_cred(&self) -> io::Result<PeerCred> {
+        match *self {
+            Unix(ref s) => unix::peer_cred(s),
+            #[cfg(feature = "websocket")]
+            Ws(ref s) => s.get_ref().peer_cred(),
+            Faulty(ref s) => s.get_ref().peer_cred(),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "peer credentials require a unix socket"))
         }
     }
 
@@ -94,7 +435,12 @@ impl NetworkStream {
         match *self {
             Tcp(ref s) => s.shutdown(how),
             Ssl(ref s) => s.get_ref().shutdown(how),
-            Mock(_) => Ok(())
+            #[cfg(feature = "websocket")]
+            Ws(ref s) => s.get_ref().shutdown(how),
+            #[cfg(unix)]
+            Unix(ref s) => s.shutdown(how),
+            Faulty(ref s) => s.get_ref().shutdown(how),
+            Mock(ref s) => s.shutdown(how)
         }
     }
 
@@ -102,7 +448,12 @@ impl NetworkStream {
         match *self {
             Tcp(ref s) => s.set_read_timeout(dur),
             Ssl(ref s) => s.get_ref().set_read_timeout(dur),
-            Mock(_) => Ok(())
+            #[cfg(feature = "websocket")]
+            Ws(ref s) => s.get_ref().set_read_timeout(dur),
+            #[cfg(unix)]
+            Unix(ref s) => s.set_read_timeout(dur),
+            Faulty(ref s) => s.get_ref().set_read_timeout(dur),
+            Mock(ref s) => s.set_read_timeout(dur)
         }
     }
 
@@ -110,7 +461,12 @@ impl NetworkStream {
         match *self {
             Tcp(ref s) => s.set_write_timeout(dur),
             Ssl(ref s) => s.get_ref().set_write_timeout(dur),
-            Mock(_) => Ok(())
+            #[cfg(feature = "websocket")]
+            Ws(ref s) => s.get_ref().set_write_timeout(dur),
+            #[cfg(unix)]
+            Unix(ref s) => s.set_write_timeout(dur),
+            Faulty(ref s) => s.get_ref().set_write_timeout(dur),
+            Mock(ref s) => s.set_write_timeout(dur)
         }
     }
 }
@@ -120,6 +476,11 @@ impl Read for NetworkStream {
         match *self {
             Tcp(ref mut s) => s.read(buf),
             Ssl(ref mut s) => s.read(buf),
+            #[cfg(feature = "websocket")]
+            Ws(ref mut s) => s.read(buf),
+            #[cfg(unix)]
+            Unix(ref mut s) => s.read(buf),
+            Faulty(ref mut s) => s.read(buf),
             Mock(ref mut s) => s.read(buf)
         }
     }
@@ -130,6 +491,11 @@ impl Write for NetworkStream {
         match *self {
             Tcp(ref mut s) => s.write(buf),
             Ssl(ref mut s) => s.write(buf),
+            #[cfg(feature = "websocket")]
+            Ws(ref mut s) => s.write(buf),
+            #[cfg(unix)]
+            Unix(ref mut s) => s.write(buf),
+            Faulty(ref mut s) => s.write(buf),
             Mock(ref mut s) => s.write(buf)
         }
     }
@@ -138,11 +504,21 @@ impl Write for NetworkStream {
         match *self {
             Tcp(ref mut s) => s.flush(),
             Ssl(ref mut s) => s.flush(),
+            #[cfg(feature = "websocket")]
+            Ws(ref mut s) => s.flush(),
+            #[cfg(unix)]
+            Unix(ref mut s) => s.flush(),
+            Faulty(ref mut s) => s.flush(),
             Mock(ref mut s) => s.flush()
         }
     }
 }
 
+#[cfg(unix)]
+fn no_inet_addr() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "unix sockets have no inet address")
+}
+
 pub type NetworkReader = BufReader<NetworkStream>;
 pub type NetworkWriter = BufWriter<NetworkStream>;
 
@@ -151,7 +527,7 @@ mod test {
     use std::net::Shutdown;
     use std::io::{Read, Write};
     use std::thread;
-    use super::{NetworkOptions, NetworkStream};
+    use super::{NetworkOptions, NetworkStream, ToHostAddrs};
     use mock::MockStream;
 
     #[test]
@@ -171,6 +547,43 @@ mod test {
         assert_eq!(req, vec![0, 1, 2, 3, 4, 5]);
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn unix_server_client_test() {
+        let path = ::std::env::temp_dir().join(format!("netopt-{}.sock", ::std::process::id()));
+        let _ = ::std::fs::remove_file(&path);
+        let mut listener = NetworkOptions::new().bind_unix(&path).unwrap();
+
+        let client_path = path.clone();
+        thread::spawn(move || {
+            let mut client = NetworkOptions::new().connect_unix(client_path).unwrap();
+            client.write_all(&[0, 1, 2, 3]).unwrap();
+            client.flush().unwrap();
+            client.shutdown(Shutdown::Both).unwrap();
+        });
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let cred = stream.peer_cred().unwrap();
+        assert_eq!(cred.uid, unsafe { ::libc::getuid() });
+        if let Some(pid) = cred.pid {
+            assert_eq!(pid as u32, ::std::process::id());
+        }
+        assert!(stream.peer_addr().is_err());
+        let mut req = Vec::new();
+        stream.read_to_end(&mut req).unwrap();
+        assert_eq!(req, vec![0, 1, 2, 3]);
+        ::std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn host_addrs_test() {
+        assert_eq!("example.com:8883".host(), Some("example.com".to_owned()));
+        assert_eq!("[::1]:8883".host(), Some("::1".to_owned()));
+        assert_eq!(("example.com", 8883).host(), Some("example.com".to_owned()));
+        assert_eq!("127.0.0.1:1883".parse::<::std::net::SocketAddr>().unwrap().host(), Some("127.0.0.1".to_owned()));
+        assert_eq!("localhost".host(), None);
+    }
+
     #[test]
     fn tcp_attach_test() {
         let mock = MockStream::with_vec(vec![0xFE, 0xFD]);
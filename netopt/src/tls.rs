@@ -0,0 +1,392 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use ssl::{SslContext, SslError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    Tls1,
+    Tls1_1,
+    Tls1_2,
+    Tls1_3
+}
+
+/// How the peer's certificate is checked.
+///
+/// A client verifies the server's chain and hostname unless the mode is
+/// `None`. A server asks for a client certificate with `Peer`, verifying it
+/// when one is sent, and rejects clients without one with `PeerRequired`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyMode {
+    None,
+    Peer,
+    PeerRequired
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "rustls", allow(dead_code))]
+pub(crate) enum Identity {
+    Pem { cert: PathBuf, key: PathBuf },
+    Pkcs12 { path: PathBuf, password: String }
+}
+
+/// Collects TLS settings, then builds a client or server `SslContext`
+/// for whichever TLS backend is compiled in.
+#[derive(Debug, Clone)]
+pub struct SslContextBuilder {
+    pub(crate) ca_files: Vec<PathBuf>,
+    pub(crate) ca_dirs: Vec<PathBuf>,
+    pub(crate) system_roots: bool,
+    pub(crate) identity: Option<Identity>,
+    pub(crate) min_version: Option<TlsVersion>,
+    pub(crate) max_version: Option<TlsVersion>,
+    pub(crate) ciphers: Option<String>,
+    pub(crate) alpn: Vec<String>,
+    pub(crate) verify: VerifyMode
+}
+
+impl Default for SslContextBuilder {
+    fn default() -> SslContextBuilder {
+        SslContextBuilder::new()
+    }
+}
+
+impl SslContextBuilder {
+    /// Nothing is trusted yet and peers are verified
+    pub fn new() -> SslContextBuilder {
+        SslContextBuilder {
+            ca_files: Vec::new(),
+            ca_dirs: Vec::new(),
+            system_roots: false,
+            identity: None,
+            min_version: None,
+            max_version: None,
+            ciphers: None,
+            alpn: Vec::new(),
+            verify: VerifyMode::Peer
+        }
+    }
+
+    /// Trusts the certificates of a PEM bundle
+    pub fn add_ca_file<P: AsRef<Path>>(&mut self, path: P) -> &mut SslContextBuilder {
+        self.ca_files.push(path.as_ref().to_path_buf()); self
+    }
+
+    /// Trusts the PEM certificates found in a directory
+    pub fn add_ca_dir<P: AsRef<Path>>(&mut self, path: P) -> &mut SslContextBuilder {
+        self.ca_dirs.push(path.as_ref().to_path_buf()); self
+    }
+
+    /// Trusts the operating system's certificate store
+    pub fn set_system_roots(&mut self, system_roots: bool) -> &mut SslContextBuilder {
+        self.system_roots = system_roots; self
+    }
+
+    pub fn set_cert_and_key<C, K>(&mut self, cert: C, key: K) -> &mut SslContextBuilder
+    where C: AsRef<Path>, K: AsRef<Path> {
+        self.identity = Some(Identity::Pem {
+            cert: cert.as_ref().to_path_buf(),
+            key: key.as_ref().to_path_buf()
+        });
+        self
+    }
+
+    /// Certificate, key and chain from a PKCS#12 archive. Only supported
+    /// by the OpenSSL backend.
+    pub fn set_pkcs12<P: AsRef<Path>>(&mut self, path: P, password: &str) -> &mut SslContextBuilder {
+        self.identity = Some(Identity::Pkcs12 {
+            path: path.as_ref().to_path_buf(),
+            password: password.to_owned()
+        });
+        self
+    }
+
+    pub fn set_min_version(&mut self, version: TlsVersion) -> &mut SslContextBuilder {
+        self.min_version = Some(version); self
+    }
+
+    pub fn set_max_version(&mut self, version: TlsVersion) -> &mut SslContextBuilder {
+        self.max_version = Some(version); self
+    }
+
+    /// Colon separated cipher suites as the backend names them: OpenSSL
+    /// cipher strings, or IANA names such as `TLS13_AES_128_GCM_SHA256`
+    /// with rustls. TLS 1.3 suites start with `TLS_` for OpenSSL.
+    pub fn set_ciphers(&mut self, ciphers: &str) -> &mut SslContextBuilder {
+        self.ciphers = Some(ciphers.to_owned()); self
+    }
+
+    /// Protocols such as `mqtt` or `x-amzn-mqtt-ca`, in order of preference
+    pub fn set_alpn_protocols(&mut self, protocols: &[&str]) -> &mut SslContextBuilder {
+        self.alpn = protocols.iter().map(|p| p.to_string()).collect(); self
+    }
+
+    pub fn set_verify(&mut self, verify: VerifyMode) -> &mut SslContextBuilder {
+        self.verify = verify; self
+    }
+
+    /// Files the contexts are built from
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.ca_files.iter().chain(self.ca_dirs.iter()).cloned().collect();
+        match self.identity {
+            Some(Identity::Pem { ref cert, ref key }) => {
+                files.push(cert.clone());
+                files.push(key.clone());
+            },
+            Some(Identity::Pkcs12 { ref path, .. }) => files.push(path.clone()),
+            None => ()
+        }
+        files
+    }
+
+    pub fn build_client(&self) -> Result<SslContext, SslError> {
+        SslContext::build_client(self)
+    }
+
+    pub fn build_server(&self) -> Result<SslContext, SslError> {
+        SslContext::build_server(self)
+    }
+}
+
+/// Swaps the TLS context of a running `NetworkListener`.
+///
+/// Handshakes started after a swap use the new context, connections already
+/// accepted keep theirs.
+#[derive(Clone)]
+pub struct TlsHandle {
+    context: Arc<RwLock<SslContext>>
+}
+
+impl TlsHandle {
+    pub(crate) fn new(context: Arc<RwLock<SslContext>>) -> TlsHandle {
+        TlsHandle {
+            context: context
+        }
+    }
+
+    pub fn get(&self) -> SslContext {
+        self.context.read().unwrap().clone()
+    }
+
+    pub fn set(&self, context: SslContext) {
+        *self.context.write().unwrap() = context;
+    }
+
+    /// Builds a new server context, keeping the current one on error
+    pub fn reload(&self, builder: &SslContextBuilder) -> Result<(), SslError> {
+        self.set(builder.build_server()?);
+        Ok(())
+    }
+
+    /// Reloads the server context whenever one of the files `builder` reads
+    /// is modified, checking every `interval`. A failed reload, e.g. a new
+    /// certificate written before its key, is retried on the next change.
+    /// The thread ends once the listener and its handles are dropped.
+    pub fn watch(&self, builder: SslContextBuilder, interval: Duration) -> JoinHandle<()> {
+        let context = Arc::downgrade(&self.context);
+        thread::spawn(move || {
+            let mut modified = modification_times(&builder);
+            loop {
+                thread::sleep(interval);
+                let context: Arc<RwLock<SslContext>> = match Weak::upgrade(&context) {
+                    Some(context) => context,
+                    None => return
+                };
+                let current = modification_times(&builder);
+                if current == modified {
+                    continue;
+                }
+                modified = current;
+                if let Ok(ssl) = builder.build_server() {
+                    *context.write().unwrap() = ssl;
+                }
+            }
+        })
+    }
+}
+
+fn modification_times(builder: &SslContextBuilder) -> Vec<Option<SystemTime>> {
+    builder.files().iter().map(|file| fs::metadata(file).and_then(|m| m.modified()).ok()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+    use ssl::{SslContext, SslStream};
+    use tcp::NetworkOptions;
+    use super::{SslContextBuilder, TlsVersion, VerifyMode};
+
+    fn cert(name: &str) -> String {
+        format!("{}/tests/certs/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn server() -> SslContextBuilder {
+        let mut builder = SslContextBuilder::new();
+        builder.set_cert_and_key(cert("server.pem"), cert("server.key"));
+        builder
+    }
+
+    fn client() -> SslContextBuilder {
+        let mut builder = SslContextBuilder::new();
+        builder.add_ca_file(cert("ca.pem"));
+        builder
+    }
+
+    fn echo(server: &SslContextBuilder, client: &SslContextBuilder, domain: &str) -> bool {
+        let server: SslContext = server.build_server().unwrap();
+        let client = client.build_client().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            if let Ok(mut stream) = server.accept(stream) {
+                let mut buf = [0; 1];
+                let _ = stream.read_exact(&mut buf).and_then(|_| stream.write_all(&buf));
+            }
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut buf = [0; 1];
+        client.connect(stream, domain)
+            .and_then(|mut stream| stream.write_all(&[7]).and_then(|_| stream.read_exact(&mut buf)))
+            .is_ok() && buf == [7]
+    }
+
+    #[test]
+    fn builder_ca_test() {
+        assert!(echo(&server(), &client(), "localhost"));
+        assert!(!echo(&server(), &client(), "example.com"));
+        assert!(!echo(&server(), &SslContextBuilder::new(), "localhost"));
+
+        let mut dir = SslContextBuilder::new();
+        dir.add_ca_dir(cert(""));
+        assert!(echo(&server(), &dir, "localhost"));
+
+        let mut unverified = SslContextBuilder::new();
+        unverified.set_verify(VerifyMode::None);
+        assert!(echo(&server(), &unverified, "example.com"));
+    }
+
+    #[test]
+    fn builder_client_cert_test() {
+        let mut server = server();
+        server.add_ca_file(cert("ca.pem")).set_verify(VerifyMode::PeerRequired);
+        let mut client = client();
+        client.set_cert_and_key(cert("client.pem"), cert("client.key"));
+        assert!(echo(&server, &client, "localhost"));
+        assert!(!echo(&server, &self::client(), "localhost"));
+
+        server.set_verify(VerifyMode::Peer);
+        assert!(echo(&server, &self::client(), "localhost"));
+    }
+
+    #[test]
+    fn builder_version_alpn_test() {
+        let mut server = server();
+        server.set_max_version(TlsVersion::Tls1_2).set_alpn_protocols(&["mqtt"]);
+        let mut client = client();
+        client.set_alpn_protocols(&["x-amzn-mqtt-ca", "mqtt"]);
+        assert!(echo(&server, &client, "localhost"));
+
+        client.set_min_version(TlsVersion::Tls1_3);
+        assert!(!echo(&server, &client, "localhost"));
+    }
+
+    #[test]
+    fn builder_error_test() {
+        let mut missing = SslContextBuilder::new();
+        missing.add_ca_file(cert("missing.pem"));
+        assert!(missing.build_client().is_err());
+
+        let mut mismatched = SslContextBuilder::new();
+        mismatched.set_cert_and_key(cert("server.pem"), cert("client.key"));
+        assert!(mismatched.build_server().is_err());
+    }
+
+    fn spawn_listener(context: SslContext) -> (SocketAddr, super::TlsHandle) {
+        let mut options = NetworkOptions::new();
+        options.tls(context);
+        let mut listener = options.bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = listener.tls_handle().unwrap();
+        thread::spawn(move || {
+            loop {
+                // failed handshakes surface as accept errors
+                if let Ok((mut stream, _)) = listener.accept() {
+                    thread::spawn(move || {
+                        let mut buf = [0; 1];
+                        while stream.read_exact(&mut buf).and_then(|_| stream.write_all(&buf)).is_ok() {}
+                    });
+                }
+            }
+        });
+        (addr, handle)
+    }
+
+    fn connect(addr: SocketAddr) -> Option<SslStream> {
+        let client = client().build_client().unwrap();
+        let mut stream = client.connect(TcpStream::connect(addr).unwrap(), "localhost").ok()?;
+        if roundtrip(&mut stream) { Some(stream) } else { None }
+    }
+
+    fn roundtrip(stream: &mut SslStream) -> bool {
+        let mut buf = [0; 1];
+        stream.write_all(&[7]).and_then(|_| stream.read_exact(&mut buf)).is_ok() && buf == [7]
+    }
+
+    fn wrong_identity() -> SslContextBuilder {
+        // issued for a client, so servers presenting it fail verification
+        let mut builder = SslContextBuilder::new();
+        builder.set_cert_and_key(cert("client.pem"), cert("client.key"));
+        builder
+    }
+
+    fn mismatched() -> SslContextBuilder {
+        let mut builder = SslContextBuilder::new();
+        builder.set_cert_and_key(cert("server.pem"), cert("client.key"));
+        builder
+    }
+
+    #[test]
+    fn tls_handle_set_test() {
+        let (addr, handle) = spawn_listener(server().build_server().unwrap());
+        let mut established = connect(addr).unwrap();
+
+        handle.reload(&wrong_identity()).unwrap();
+        assert!(connect(addr).is_none());
+        assert!(roundtrip(&mut established));
+
+        assert!(handle.reload(&mismatched()).is_err());
+        handle.set(server().build_server().unwrap());
+        assert!(connect(addr).is_some());
+    }
+
+    #[test]
+    fn tls_handle_watch_test() {
+        let dir = ::std::env::temp_dir().join(format!("netopt-watch-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::copy(cert("client.pem"), dir.join("cert.pem")).unwrap();
+        fs::copy(cert("client.key"), dir.join("key.pem")).unwrap();
+        let mut watched = SslContextBuilder::new();
+        watched.set_cert_and_key(dir.join("cert.pem"), dir.join("key.pem"));
+
+        let (addr, handle) = spawn_listener(watched.build_server().unwrap());
+        handle.watch(watched, Duration::from_millis(20));
+        assert!(connect(addr).is_none());
+
+        fs::copy(cert("server.pem"), dir.join("cert.pem")).unwrap();
+        fs::copy(cert("server.key"), dir.join("key.pem")).unwrap();
+        let rotated = (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(20));
+            connect(addr).is_some()
+        });
+        assert!(rotated);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
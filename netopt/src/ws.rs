@@ -0,0 +1,224 @@
+use std::io::{self, Read, Write};
+use tungstenite::{self, Message, WebSocket};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::handshake::HandshakeError;
+use tungstenite::handshake::server::{Request, Response, ErrorResponse};
+use tungstenite::http::HeaderValue;
+
+use tcp::NetworkStream;
+
+const SUBPROTOCOL_HEADER: &str = "Sec-WebSocket-Protocol";
+const MQTT_SUBPROTOCOL: &str = "mqtt";
+
+/// Where to upgrade the connection to a WebSocket.
+#[derive(Debug, Clone)]
+pub struct WsOptions {
+    /// Request path, `/mqtt` by default
+    pub path: String,
+    /// `Host` header, the peer address (or `localhost`) is used when it isn't set
+    pub host: Option<String>
+}
+
+impl WsOptions {
+    pub fn new<P: Into<String>>(path: P) -> WsOptions {
+        WsOptions {
+            path: path.into(),
+            host: None
+        }
+    }
+}
+
+impl Default for WsOptions {
+    fn default() -> WsOptions {
+        WsOptions::new("/mqtt")
+    }
+}
+
+/// MQTT bytes carried in binary WebSocket messages.
+///
+/// Everything written up to a `flush` goes out as one message. Incoming
+/// messages are read as a plain byte stream, regardless of how packets are
+/// split between them.
+pub struct WsStream {
+    socket: WebSocket<NetworkStream>,
+    incoming: Vec<u8>,
+    position: usize,
+    outgoing: Vec<u8>
+}
+
+impl WsStream {
+    /// Performs the client side of the HTTP upgrade over `stream`.
+    pub fn connect(stream: NetworkStream, options: &WsOptions) -> io::Result<WsStream> {
+        let host = match options.host {
+            Some(ref host) => host.clone(),
+            None => stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_else(|_| "localhost".to_owned())
+        };
+        let mut request = format!("ws://{}{}", host, options.path).into_client_request().map_err(to_io_error)?;
+        request.headers_mut().insert(SUBPROTOCOL_HEADER, HeaderValue::from_static(MQTT_SUBPROTOCOL));
+
+        match tungstenite::client(request, stream) {
+            Ok((socket, _)) => Ok(WsStream::new(socket)),
+            Err(HandshakeError::Failure(err)) => Err(to_io_error(err)),
+            Err(HandshakeError::Interrupted(_)) => Err(io::Error::new(io::ErrorKind::TimedOut, "websocket handshake interrupted"))
+        }
+    }
+
+    /// Performs the server side of the HTTP upgrade over `stream`, agreeing
+    /// on the `mqtt` subprotocol when the client asks for it.
+    #[allow(clippy::result_large_err)]
+    pub fn accept(stream: NetworkStream) -> io::Result<WsStream> {
+        let callback = |request: &Request, mut response: Response| -> Result<Response, ErrorResponse> {
+            let mqtt = request.headers().get_all(SUBPROTOCOL_HEADER).iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .any(|protocol| protocol.trim() == MQTT_SUBPROTOCOL);
+            if mqtt {
+                response.headers_mut().insert(SUBPROTOCOL_HEADER, HeaderValue::from_static(MQTT_SUBPROTOCOL));
+            }
+            Ok(response)
+        };
+
+        match tungstenite::accept_hdr(stream, callback) {
+            Ok(socket) => Ok(WsStream::new(socket)),
+            Err(HandshakeError::Failure(err)) => Err(io::Error::new(io::ErrorKind::ConnectionAborted, err)),
+            Err(HandshakeError::Interrupted(_)) => Err(io::Error::new(io::ErrorKind::TimedOut, "websocket handshake interrupted"))
+        }
+    }
+
+    fn new(socket: WebSocket<NetworkStream>) -> WsStream {
+        WsStream {
+            socket: socket,
+            incoming: Vec::new(),
+            position: 0,
+            outgoing: Vec::new()
+        }
+    }
+
+    pub fn get_ref(&self) -> &NetworkStream {
+        self.socket.get_ref()
+    }
+}
+
+impl Read for WsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.incoming.len() {
+            match self.socket.read() {
+                Ok(Message::Binary(data)) => {
+                    self.incoming = data.to_vec();
+                    self.position = 0;
+                },
+                Ok(Message::Text(_)) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "MQTT over websocket requires binary messages"));
+                },
+                // pings are answered by tungstenite itself
+                Ok(Message::Ping(_)) | Ok(Message::Pong(_)) | Ok(Message::Frame(_)) => (),
+                Ok(Message::Close(_)) |
+                Err(tungstenite::Error::ConnectionClosed) |
+                Err(tungstenite::Error::AlreadyClosed) => return Ok(0),
+                Err(err) => return Err(to_io_error(err))
+            }
+        }
+        let len = buf.len().min(self.incoming.len() - self.position);
+        buf[..len].copy_from_slice(&self.incoming[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl Write for WsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.outgoing.is_empty() {
+            let data = ::std::mem::take(&mut self.outgoing);
+            self.socket.write(Message::binary(data)).map_err(to_io_error)?;
+        }
+        self.socket.flush().map_err(to_io_error)
+    }
+}
+
+fn to_io_error(err: tungstenite::Error) -> io::Error {
+    match err {
+        tungstenite::Error::Io(err) => err,
+        tungstenite::Error::ConnectionClosed |
+        tungstenite::Error::AlreadyClosed => io::Error::new(io::ErrorKind::ConnectionAborted, err),
+        err => io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use super::WsOptions;
+    use tcp::NetworkOptions;
+
+    #[test]
+    fn ws_server_client_test() {
+        let mut options = NetworkOptions::new();
+        options.websocket(WsOptions::default());
+        let mut listener = options.bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut client = options.connect(addr).unwrap();
+            client.write_all(&[0x10, 0x02]).unwrap();
+            client.write_all(&[0x00, 0x04]).unwrap();
+            client.flush().unwrap();
+            let mut buf = [0; 2];
+            client.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [0x20, 0x02]);
+        });
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x10, 0x02, 0x00, 0x04]);
+        stream.write_all(&[0x20, 0x02]).unwrap();
+        stream.flush().unwrap();
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn ws_fragmented_frames_test() {
+        let mut options = NetworkOptions::new();
+        options.websocket(WsOptions::default());
+        let mut listener = options.bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"GET /mqtt HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                Upgrade: websocket\r\n\
+                Connection: Upgrade\r\n\
+                Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                Sec-WebSocket-Protocol: mqtt\r\n\
+                Sec-WebSocket-Version: 13\r\n\r\n").unwrap();
+            let mut response = Vec::new();
+            let mut byte = [0; 1];
+            while !response.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).unwrap();
+                response.push(byte[0]);
+            }
+            let response = String::from_utf8(response).unwrap().to_lowercase();
+            assert!(response.contains("sec-websocket-protocol: mqtt"));
+
+            // binary frame without FIN, then a masked continuation frame with FIN
+            stream.write_all(&[0x02, 0x82, 0, 0, 0, 0, 0xC0, 0x00]).unwrap();
+            stream.write_all(&[0x80, 0x82, 0, 0, 0, 0, 0xE0, 0x00]).unwrap();
+            stream.flush().unwrap();
+            stream
+        });
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0xC0, 0x00, 0xE0, 0x00]);
+        client.join().unwrap();
+    }
+}
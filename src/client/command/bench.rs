@@ -0,0 +1,417 @@
+use std::collections::HashSet;
+use std::process::exit;
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use mqtt3::{QoS, Protocol, SubscribeTopic};
+use netopt::{NetworkOptions, SslContext, Proxy};
+use mqttc::{PubSub, Client, ClientOptions, PubOpt, BrokerUrl};
+use serde_json;
+use super::{Command, LocalStorage};
+use client::logger::set_stdout_logger;
+
+/// Publisher index, sequence number and send time in microseconds
+const HEADER_LEN: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct BenchCommand {
+    // Load
+    pub publishers: usize,
+    pub subscribers: usize,
+    // messages per second across all publishers
+    pub rate: Option<f64>,
+    pub qos: QoS,
+    pub size: usize,
+    // `{client}` and `{seq}` are replaced per message
+    pub topic: String,
+    pub duration: Duration,
+    pub interval: Duration,
+
+    // Connection
+    // takes the place of address and port
+    pub url: Option<BrokerUrl>,
+    pub address: String,
+    pub port: u16,
+    pub keep_alive: u16,
+
+    // preferences
+    pub debug: bool,
+    pub protocol: Protocol,
+
+    // Authorization
+    pub username: Option<String>,
+    pub password: Option<String>,
+
+    // SSL/TLS option
+    pub tls: Option<SslContext>,
+
+    pub proxy: Option<Proxy>
+}
+
+impl Default for BenchCommand {
+    fn default() -> BenchCommand {
+        BenchCommand {
+            publishers: 1,
+            subscribers: 1,
+            rate: None,
+            qos: QoS::AtMostOnce,
+            size: 64,
+            topic: "bench/{client}/{seq}".to_string(),
+            duration: Duration::from_secs(10),
+            interval: Duration::from_secs(1),
+
+            url: None,
+            address: "localhost".to_string(),
+            port: 1883,
+            keep_alive: 30,
+
+            debug: false,
+            protocol: Protocol::MQTT(4),
+
+            username: None,
+            password: None,
+
+            tls: None,
+            proxy: None
+        }
+    }
+}
+
+/// Counters shared by all connections. Latencies are in microseconds.
+#[derive(Debug, Default)]
+struct Stats {
+    sent: u64,
+    received: u64,
+    duplicates: u64,
+    errors: u64,
+    latencies: Vec<u64>
+}
+
+impl Command for BenchCommand {
+    fn run(&self) -> ! {
+        if self.debug {
+            set_stdout_logger().unwrap();
+        }
+
+        debug!("{:?}", self);
+        let stats = Arc::new(Mutex::new(Stats::default()));
+        let ready = Arc::new(Barrier::new(self.subscribers + 1));
+        for n in 0..self.subscribers {
+            let (command, stats, ready) = (self.clone(), stats.clone(), ready.clone());
+            thread::spawn(move || command.subscriber(n, stats, ready));
+        }
+        // publish once every subscription is acknowledged
+        ready.wait();
+
+        let start = Instant::now();
+        for n in 0..self.publishers {
+            let (command, stats) = (self.clone(), stats.clone());
+            thread::spawn(move || command.publisher(n, start, stats));
+        }
+
+        let mut total = Vec::new();
+        let mut last = (0, 0);
+        let mut elapsed = Duration::from_secs(0);
+        // let the last messages arrive
+        let end = self.duration + Duration::from_secs(1);
+        while elapsed < end {
+            thread::sleep(self.interval.min(end - elapsed));
+            elapsed = start.elapsed();
+
+            let mut stats = stats.lock().unwrap();
+            let mut latencies: Vec<u64> = stats.latencies.drain(..).collect();
+            latencies.sort();
+            let secs = self.interval.as_secs_f64();
+            eprintln!("{:>7.1}s  sent {:>8} {:>9.1}/s  received {:>8} {:>9.1}/s  latency p50 {} p99 {} ms",
+                      elapsed.as_secs_f64(),
+                      stats.sent, (stats.sent - last.0) as f64 / secs,
+                      stats.received, (stats.received - last.1) as f64 / secs,
+                      millis(percentile(&latencies, 50.0)), millis(percentile(&latencies, 99.0)));
+            last = (stats.sent, stats.received);
+            total.extend(latencies);
+        }
+
+        total.sort();
+        let stats = stats.lock().unwrap();
+        println!("{}", self.report(&stats, &total, elapsed));
+        exit(0);
+    }
+}
+
+impl BenchCommand {
+    // `SslContext` is uninhabited without a TLS backend
+    #[cfg_attr(not(any(feature = "ssl", feature = "rustls")), allow(unreachable_code))]
+    fn connect(&self, client_id: String) -> Client {
+        let mut netopt = NetworkOptions::new();
+        // small messages would otherwise wait for delayed ACKs
+        netopt.nodelay(true);
+        if let Some(ref tls) = self.tls {
+            netopt.tls(tls.clone());
+        };
+        if let Some(ref proxy) = self.proxy {
+            netopt.proxy(proxy.clone());
+        };
+
+        let mut opts = ClientOptions::new();
+        opts.set_protocol(self.protocol);
+        opts.set_keep_alive(self.keep_alive);
+        opts.set_clean_session(true);
+        opts.set_client_id(client_id);
+        opts.set_outgoing_store(LocalStorage::new());
+        opts.set_incomming_store(LocalStorage::new());
+        if let Some(ref username) = self.username {
+            opts.set_username(username.clone());
+        };
+        if let Some(ref password) = self.password {
+            opts.set_password(password.clone());
+        };
+
+        match self.url {
+            Some(ref url) => opts.connect_url(url, netopt),
+            None => opts.connect(format!("{}:{}", self.address, self.port).as_str(), netopt)
+        }.expect("Can't connect to server")
+    }
+
+    /// Publishes its share of `rate` until `duration` is over, waiting for
+    /// the acknowledgement of each message like `publish` does
+    fn publisher(&self, n: usize, start: Instant, stats: Arc<Mutex<Stats>>) {
+        let mut client = self.connect(format!("mqttc-bench-pub-{}-{}", ::std::process::id(), n));
+        let interval = self.rate.map(|rate| self.publishers as f64 / rate);
+        let mut payload = vec![0; self.size.max(HEADER_LEN)];
+        let mut seq: u64 = 0;
+        while start.elapsed() < self.duration {
+            if let Some(interval) = interval {
+                let due = Duration::from_secs_f64(interval * seq as f64);
+                let elapsed = start.elapsed();
+                if due > elapsed {
+                    thread::sleep(due - elapsed);
+                }
+            }
+
+            payload[..4].copy_from_slice(&(n as u32).to_be_bytes());
+            payload[4..12].copy_from_slice(&seq.to_be_bytes());
+            payload[12..20].copy_from_slice(&now_micros().to_be_bytes());
+            let topic = self.topic.replace("{client}", &n.to_string()).replace("{seq}", &seq.to_string());
+            let sent = client.publish(topic, payload.clone(), PubOpt::new(self.qos, false))
+                .and_then(|_| {
+                    if self.qos != QoS::AtMostOnce {
+                        while client.await()?.is_some() {};
+                    }
+                    Ok(())
+                });
+
+            let mut stats = stats.lock().unwrap();
+            match sent {
+                Ok(_) => stats.sent += 1,
+                Err(err) => {
+                    stats.errors += 1;
+                    error!("publisher {}: {:?}", n, err);
+                    return;
+                }
+            }
+            seq += 1;
+        }
+    }
+
+    /// Records the latency of every message published during the run
+    fn subscriber(&self, n: usize, stats: Arc<Mutex<Stats>>, ready: Arc<Barrier>) {
+        let mut client = self.connect(format!("mqttc-bench-sub-{}-{}", ::std::process::id(), n));
+        client.subscribe(SubscribeTopic { topic_path: topic_filter(&self.topic), qos: self.qos }).expect("Can't subscribe");
+        // SUBACK
+        while client.await().expect("Can't subscribe").is_some() {};
+        ready.wait();
+
+        let mut seen = HashSet::new();
+        loop {
+            match client.await() {
+                Ok(Some(message)) => {
+                    let received = now_micros();
+                    if message.qos == QoS::ExactlyOnce {
+                        let _ = client.complete(message.pid.unwrap());
+                    }
+                    let header = match parse_header(&message.payload) {
+                        Some(header) => header,
+                        // not ours
+                        None => continue
+                    };
+
+                    let mut stats = stats.lock().unwrap();
+                    if seen.insert((header.0, header.1)) {
+                        stats.received += 1;
+                        stats.latencies.push(received.saturating_sub(header.2));
+                    } else {
+                        stats.duplicates += 1;
+                    }
+                },
+                Ok(None) => (),
+                Err(err) => {
+                    stats.lock().unwrap().errors += 1;
+                    error!("subscriber {}: {:?}", n, err);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// The summary as JSON. Throughput counts the whole run, `elapsed`,
+    /// including the time given to the last messages to arrive.
+    fn report(&self, stats: &Stats, latencies: &[u64], elapsed: Duration) -> String {
+        let expected = stats.sent * self.subscribers as u64;
+        let loss = if expected > 0 {
+            1.0 - stats.received as f64 / expected as f64
+        } else {
+            0.0
+        };
+        let secs = elapsed.as_secs_f64();
+        let mean = if latencies.is_empty() {
+            None
+        } else {
+            Some(latencies.iter().sum::<u64>() / latencies.len() as u64)
+        };
+        let report = Report {
+            publishers: self.publishers,
+            subscribers: self.subscribers,
+            qos: self.qos.to_u8(),
+            size: self.size.max(HEADER_LEN),
+            duration: self.duration.as_secs_f64(),
+            elapsed: secs,
+            sent: stats.sent,
+            received: stats.received,
+            expected: expected,
+            duplicates: stats.duplicates,
+            errors: stats.errors,
+            loss: loss,
+            throughput: Throughput {
+                sent: stats.sent as f64 / secs,
+                received: stats.received as f64 / secs
+            },
+            latency_ms: Latency {
+                min: latencies.first().map(|&micros| micros as f64 / 1000.0),
+                p50: percentile(latencies, 50.0).map(|micros| micros as f64 / 1000.0),
+                p90: percentile(latencies, 90.0).map(|micros| micros as f64 / 1000.0),
+                p99: percentile(latencies, 99.0).map(|micros| micros as f64 / 1000.0),
+                max: latencies.last().map(|&micros| micros as f64 / 1000.0),
+                mean: mean.map(|micros| micros as f64 / 1000.0)
+            }
+        };
+        serde_json::to_string(&report).expect("a report is always valid JSON")
+    }
+}
+
+/// Printed when the run is over
+#[derive(Serialize)]
+struct Report {
+    publishers: usize,
+    subscribers: usize,
+    qos: u8,
+    size: usize,
+    // configured and measured seconds
+    duration: f64,
+    elapsed: f64,
+    sent: u64,
+    received: u64,
+    expected: u64,
+    duplicates: u64,
+    errors: u64,
+    loss: f64,
+    // messages per second
+    throughput: Throughput,
+    latency_ms: Latency
+}
+
+#[derive(Serialize)]
+struct Throughput {
+    sent: f64,
+    received: f64
+}
+
+/// `null` when nothing was received
+#[derive(Serialize)]
+struct Latency {
+    min: Option<f64>,
+    p50: Option<f64>,
+    p90: Option<f64>,
+    p99: Option<f64>,
+    max: Option<f64>,
+    mean: Option<f64>
+}
+
+/// Subscription matching every topic of the pattern, a level with a placeholder becomes `+`
+pub fn topic_filter(pattern: &str) -> String {
+    pattern.split('/')
+        .map(|level| if level.contains("{client}") || level.contains("{seq}") { "+" } else { level })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn parse_header(payload: &[u8]) -> Option<(u32, u64, u64)> {
+    if payload.len() < HEADER_LEN {
+        return None;
+    }
+    let mut client = [0; 4];
+    let mut seq = [0; 8];
+    let mut sent = [0; 8];
+    client.copy_from_slice(&payload[..4]);
+    seq.copy_from_slice(&payload[4..12]);
+    sent.copy_from_slice(&payload[12..20]);
+    Some((u32::from_be_bytes(client), u64::from_be_bytes(seq), u64::from_be_bytes(sent)))
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64
+}
+
+/// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[u64], p: f64) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+fn millis(micros: Option<u64>) -> String {
+    match micros {
+        Some(micros) => format!("{:.3}", micros as f64 / 1000.0),
+        None => "null".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use serde_json::{self, Value};
+    use super::{BenchCommand, Stats, topic_filter, percentile};
+
+    #[test]
+    fn bench_topic_filter_test() {
+        assert_eq!(topic_filter("bench/{client}/{seq}"), "bench/+/+");
+        assert_eq!(topic_filter("bench/c{client}/data"), "bench/+/data");
+        assert_eq!(topic_filter("bench"), "bench");
+    }
+
+    #[test]
+    fn bench_percentile_test() {
+        let values: Vec<u64> = (1..101).collect();
+        assert_eq!(percentile(&values, 50.0), Some(50));
+        assert_eq!(percentile(&values, 99.0), Some(99));
+        assert_eq!(percentile(&values, 100.0), Some(100));
+        assert_eq!(percentile(&[7], 1.0), Some(7));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+    #[test]
+    fn bench_report_test() {
+        let command = BenchCommand::default();
+        let stats = Stats { sent: 100, received: 90, ..Stats::default() };
+        let report: Value = serde_json::from_str(&command.report(&stats, &[1000, 2000, 3000], Duration::from_secs(20))).unwrap();
+        assert_eq!(report["duration"], 10.0);
+        assert_eq!(report["throughput"]["sent"], 5.0);
+        assert_eq!(report["throughput"]["received"], 4.5);
+        assert!((report["loss"].as_f64().unwrap() - 0.1).abs() < 1e-9);
+        assert_eq!(report["latency_ms"]["p50"], 2.0);
+        assert_eq!(report["latency_ms"]["mean"], 2.0);
+
+        let report: Value = serde_json::from_str(&command.report(&Stats::default(), &[], Duration::from_secs(11))).unwrap();
+        assert!(report["latency_ms"]["max"].is_null());
+    }
+}
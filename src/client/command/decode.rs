@@ -0,0 +1,106 @@
+use std::io::{self, Read};
+use std::fs::OpenOptions;
+use std::process::exit;
+use mqtt3::{self, PayloadFormat};
+use super::Command;
+
+#[derive(Debug, Clone)]
+pub enum DecodeInput {
+    // hex dump given on the command line
+    Hex(String),
+    // hex dump read from stdin
+    HexStdin,
+    RawStdin,
+    File(String)
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodeCommand {
+    pub input: DecodeInput,
+    pub format: PayloadFormat
+}
+
+impl Default for DecodeCommand {
+    fn default() -> DecodeCommand {
+        DecodeCommand {
+            input: DecodeInput::HexStdin,
+            format: PayloadFormat::Utf8
+        }
+    }
+}
+
+impl Command for DecodeCommand {
+    fn run(&self) -> ! {
+        let bytes = match self.read_input() {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                println!("Can't read packet bytes: {}", err);
+                exit(66);
+            }
+        };
+
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let dissection = mqtt3::dissect(&bytes[offset..], self.format);
+            if offset > 0 {
+                println!();
+            }
+            print!("{}", dissection);
+            if dissection.is_malformed() {
+                exit(65); // data format error
+            }
+            offset += dissection.len();
+        }
+
+        exit(0);
+    }
+}
+
+impl DecodeCommand {
+    fn read_input(&self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        match self.input {
+            DecodeInput::Hex(ref hex) => return parse_hex(hex),
+            DecodeInput::HexStdin => {
+                io::stdin().read_to_end(&mut data)?;
+                return parse_hex(&String::from_utf8_lossy(&data));
+            },
+            DecodeInput::RawStdin => {
+                io::stdin().read_to_end(&mut data)?;
+            },
+            DecodeInput::File(ref file) => {
+                let mut f = OpenOptions::new().read(true).open(file)?;
+                f.read_to_end(&mut data)?;
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Accepts `30 05 00 03`, `0x30,0x05` or `30050003` style dumps.
+pub fn parse_hex(text: &str) -> io::Result<Vec<u8>> {
+    let digits: String = text.split(|c: char| c.is_whitespace() || c == ',')
+        .map(|token| token.trim_start_matches("0x").trim_start_matches("0X"))
+        .collect();
+    if !digits.is_ascii() || digits.len() % 2 == 1 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "expected pairs of hex digits"));
+    }
+    (0..digits.len()).step_by(2).map(|i| {
+        u8::from_str_radix(&digits[i..i + 2], 16)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("invalid hex byte {:?}", &digits[i..i + 2])))
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_hex;
+
+    #[test]
+    fn parse_hex_test() {
+        assert_eq!(parse_hex("30 05\n00 03").unwrap(), vec![0x30, 0x05, 0x00, 0x03]);
+        assert_eq!(parse_hex("0x30,0x05").unwrap(), vec![0x30, 0x05]);
+        assert_eq!(parse_hex("e000").unwrap(), vec![0xe0, 0x00]);
+        assert!(parse_hex("e00").is_err());
+        assert!(parse_hex("zz").is_err());
+    }
+}
@@ -0,0 +1,516 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+use std::process::exit;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crossterm::{cursor, event, queue, terminal};
+use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use mqtt3::{Message, QoS, SubscribeTopic};
+use mqttc::{PubSub, Client, ClientState};
+use super::Command;
+use super::record::BrokerOptions;
+use client::logger::set_stdout_logger;
+
+/// How long the connection is read before keys are handled and the
+/// screen is drawn again
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Shorter rests of the interval are left for the next one
+const MIN_POLL: Duration = Duration::from_millis(10);
+
+/// Rates are messages per second over windows this long
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Subscribes to a filter and shows the topics below it as a tree.
+#[derive(Debug, Clone)]
+pub struct ExploreCommand {
+    pub filter: String,
+    pub qos: QoS,
+    // payloads kept per topic
+    pub history: usize,
+    pub connection: BrokerOptions,
+    pub debug: bool
+}
+
+impl Default for ExploreCommand {
+    fn default() -> ExploreCommand {
+        ExploreCommand {
+            filter: "#".to_string(),
+            qos: QoS::AtMostOnce,
+            history: 20,
+            connection: BrokerOptions::default(),
+            debug: false
+        }
+    }
+}
+
+impl Command for ExploreCommand {
+    fn run(&self) -> ! {
+        if self.debug {
+            set_stdout_logger().unwrap();
+        }
+
+        debug!("{:?}", self);
+        let mut client = self.connection.connect().expect("Can't connect to server");
+        client.subscribe(SubscribeTopic { topic_path: self.filter.clone(), qos: self.qos }).expect("Can't subscribe");
+
+        if let Err(err) = terminal::enable_raw_mode() {
+            println!("explore needs a terminal: {}", err);
+            exit(74);
+        }
+        let mut out = io::stdout();
+        let _ = queue!(out, terminal::EnterAlternateScreen, cursor::Hide);
+
+        let mut view = View::new(self.history, format!("{} {}", self.connection.target(), self.filter));
+        let result = self.explore(&mut client, &mut view, &mut out);
+
+        let _ = queue!(out, ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = out.flush();
+        let _ = terminal::disable_raw_mode();
+        match result {
+            Ok(()) => {
+                let _ = client.disconnect();
+                exit(0);
+            },
+            Err(err) => {
+                println!("{}", err);
+                exit(74);
+            }
+        }
+    }
+}
+
+impl ExploreCommand {
+    /// Reads messages and keys until `q`
+    fn explore<W: Write>(&self, client: &mut Client, view: &mut View, out: &mut W) -> io::Result<()> {
+        let mut online = true;
+        let mut dirty = true;
+        loop {
+            if online {
+                let deadline = Instant::now() + POLL_INTERVAL;
+                let mut left = POLL_INTERVAL;
+                while left >= MIN_POLL {
+                    match client.poll(left) {
+                        Ok(Some(message)) => {
+                            if message.qos == QoS::ExactlyOnce {
+                                let _ = client.complete(message.pid.unwrap());
+                            }
+                            view.tree.insert(&message, SystemTime::now());
+                            dirty = true;
+                        },
+                        Ok(None) => (),
+                        Err(err) => {
+                            view.status = format!("disconnected: {}", err);
+                            online = false;
+                            dirty = true;
+                            break;
+                        }
+                    }
+                    left = deadline.saturating_duration_since(Instant::now());
+                }
+                online = online && client.state() == ClientState::Connected;
+            }
+
+            // rates change even when nothing arrives
+            if dirty || view.tree.tick(Instant::now()) {
+                view.draw(out)?;
+                dirty = false;
+            }
+
+            let wait = if online { Duration::from_millis(0) } else { POLL_INTERVAL };
+            while event::poll(wait)? {
+                match event::read()? {
+                    Event::Key(key) if key.kind != KeyEventKind::Release => {
+                        let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                        if ctrl_c || key.code == KeyCode::Char('q') || key.code == KeyCode::Esc {
+                            return Ok(());
+                        }
+                        view.key(key.code);
+                        dirty = true;
+                    },
+                    Event::Resize(_, _) => dirty = true,
+                    _ => ()
+                }
+                if !online {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// A payload as it arrived
+#[derive(Debug, Clone)]
+struct Payload {
+    received: SystemTime,
+    data: Arc<Vec<u8>>,
+    retained: bool
+}
+
+/// What arrived on one topic
+#[derive(Debug, Clone)]
+struct Topic {
+    count: u64,
+    retained: bool,
+    // newest first
+    history: VecDeque<Payload>,
+    window: (Instant, u64),
+    rate: f64
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    children: BTreeMap<String, Node>,
+    topic: Option<Topic>,
+    expanded: bool
+}
+
+impl Node {
+    /// Messages on this topic and every topic below it
+    fn count(&self) -> u64 {
+        self.topic.as_ref().map_or(0, |topic| topic.count) + self.children.values().map(Node::count).sum::<u64>()
+    }
+
+    fn rate(&self) -> f64 {
+        self.topic.as_ref().map_or(0.0, |topic| topic.rate) + self.children.values().map(Node::rate).sum::<f64>()
+    }
+
+    fn topics(&self) -> usize {
+        self.topic.iter().count() + self.children.values().map(Node::topics).sum::<usize>()
+    }
+
+    fn tick(&mut self, now: Instant) -> bool {
+        let mut changed = false;
+        if let Some(ref mut topic) = self.topic {
+            let elapsed = now.duration_since(topic.window.0);
+            if elapsed >= RATE_WINDOW {
+                let rate = topic.window.1 as f64 / elapsed.as_secs_f64();
+                changed = rate != topic.rate;
+                topic.rate = rate;
+                topic.window = (now, 0);
+            }
+        }
+        for child in self.children.values_mut() {
+            changed |= child.tick(now);
+        }
+        changed
+    }
+}
+
+/// Topic levels as a tree, folded until expanded
+#[derive(Debug, Clone)]
+struct Tree {
+    root: Node,
+    history: usize
+}
+
+/// A visible line of the tree
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    path: Vec<String>,
+    has_children: bool,
+    expanded: bool
+}
+
+impl Tree {
+    fn new(history: usize) -> Tree {
+        Tree {
+            root: Node::default(),
+            history: history
+        }
+    }
+
+    fn insert(&mut self, message: &Message, received: SystemTime) {
+        let mut node = &mut self.root;
+        for level in message.topic.path.split('/') {
+            node = node.children.entry(level.to_string()).or_default();
+        }
+        let topic = node.topic.get_or_insert_with(|| Topic {
+            count: 0,
+            retained: false,
+            history: VecDeque::new(),
+            window: (Instant::now(), 0),
+            rate: 0.0
+        });
+        topic.count += 1;
+        topic.window.1 += 1;
+        topic.retained = message.retain;
+        topic.history.push_front(Payload {
+            received: received,
+            data: message.payload.clone(),
+            retained: message.retain
+        });
+        topic.history.truncate(self.history.max(1));
+    }
+
+    fn tick(&mut self, now: Instant) -> bool {
+        self.root.tick(now)
+    }
+
+    fn get(&self, path: &[String]) -> Option<&Node> {
+        path.iter().try_fold(&self.root, |node, level| node.children.get(level))
+    }
+
+    fn get_mut(&mut self, path: &[String]) -> Option<&mut Node> {
+        path.iter().try_fold(&mut self.root, |node, level| node.children.get_mut(level))
+    }
+
+    /// Rows in display order, below expanded nodes only
+    fn rows(&self) -> Vec<Row> {
+        let mut rows = Vec::new();
+        let mut path = Vec::new();
+        collect(&self.root, &mut path, &mut rows);
+        rows
+    }
+}
+
+fn collect(node: &Node, path: &mut Vec<String>, rows: &mut Vec<Row>) {
+    for (name, child) in &node.children {
+        path.push(name.clone());
+        rows.push(Row {
+            path: path.clone(),
+            has_children: !child.children.is_empty(),
+            expanded: child.expanded
+        });
+        if child.expanded {
+            collect(child, path, rows);
+        }
+        path.pop();
+    }
+}
+
+/// The tree with a selection, and what is drawn for them
+struct View {
+    tree: Tree,
+    title: String,
+    status: String,
+    selected: Option<Vec<String>>,
+    scroll: usize
+}
+
+impl View {
+    fn new(history: usize, title: String) -> View {
+        View {
+            tree: Tree::new(history),
+            title: title,
+            status: "connected".to_string(),
+            selected: None,
+            scroll: 0
+        }
+    }
+
+    /// Index of the selected row, the first one when the selection went away
+    fn position(&self, rows: &[Row]) -> usize {
+        self.selected.as_ref()
+            .and_then(|selected| rows.iter().position(|row| &row.path == selected))
+            .unwrap_or(0)
+    }
+
+    fn key(&mut self, code: KeyCode) {
+        let rows = self.tree.rows();
+        if rows.is_empty() {
+            return;
+        }
+        let index = self.position(&rows);
+        let row = &rows[index];
+        let select = match code {
+            KeyCode::Up | KeyCode::Char('k') => index.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => (index + 1).min(rows.len() - 1),
+            KeyCode::PageUp => index.saturating_sub(10),
+            KeyCode::PageDown => (index + 10).min(rows.len() - 1),
+            KeyCode::Home | KeyCode::Char('g') => 0,
+            KeyCode::End | KeyCode::Char('G') => rows.len() - 1,
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Enter | KeyCode::Char(' ') if row.has_children => {
+                let expand = code != KeyCode::Char(' ') || !row.expanded;
+                if let Some(node) = self.tree.get_mut(&row.path) {
+                    node.expanded = expand;
+                }
+                index
+            },
+            KeyCode::Left | KeyCode::Char('h') => {
+                if row.expanded {
+                    if let Some(node) = self.tree.get_mut(&row.path) {
+                        node.expanded = false;
+                    }
+                    index
+                } else {
+                    // to the parent
+                    let parent = &row.path[..row.path.len() - 1];
+                    rows.iter().position(|row| row.path == parent).unwrap_or(index)
+                }
+            },
+            _ => index
+        };
+        self.selected = Some(rows[select].path.clone());
+    }
+
+    fn draw<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let rows = self.tree.rows();
+        let index = self.position(&rows);
+        if self.selected.is_none() && !rows.is_empty() {
+            self.selected = Some(rows[0].path.clone());
+        }
+
+        // header, tree, separator, details
+        let details = (height / 3).max(4);
+        let lines = height.saturating_sub(details + 2);
+        if index < self.scroll {
+            self.scroll = index;
+        } else if lines > 0 && index >= self.scroll + lines {
+            self.scroll = index + 1 - lines;
+        }
+
+        queue!(out, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0),
+               SetAttribute(Attribute::Reverse),
+               Print(fit(&format!(" {}  {} topics  {}  q quits, arrows move, space folds",
+                                  self.title, self.tree.root.topics(), self.status), width)),
+               SetAttribute(Attribute::Reset))?;
+
+        for (line, row) in rows.iter().enumerate().skip(self.scroll).take(lines) {
+            let node = match self.tree.get(&row.path) {
+                Some(node) => node,
+                None => continue
+            };
+            let marker = match (row.has_children, row.expanded) {
+                (false, _) => ' ',
+                (true, false) => '+',
+                (true, true) => '-'
+            };
+            let name = format!("{}{} {}", "  ".repeat(row.path.len() - 1), marker, row.path.last().unwrap());
+            let mut stats = format!("{:>8} {:>7.1}/s", node.count(), node.rate());
+            if let Some(ref topic) = node.topic {
+                if topic.retained {
+                    stats.push_str(" R");
+                } else {
+                    stats.push_str("  ");
+                }
+                if let Some(last) = topic.history.front() {
+                    stats.push_str("  ");
+                    stats.push_str(&show(&last.data));
+                }
+            }
+            queue!(out, cursor::MoveTo(0, (line - self.scroll + 1) as u16))?;
+            if line == index {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            let color = if node.topic.is_some() { Color::Cyan } else { Color::Green };
+            queue!(out, SetForegroundColor(color), Print(fit(&format!("{:<32}", name), width)), ResetColor)?;
+            if width > 32 {
+                queue!(out, Print(fit(&format!(" {}", stats), width - 32)))?;
+            }
+            queue!(out, SetAttribute(Attribute::Reset))?;
+        }
+
+        let top = height.saturating_sub(details + 1);
+        queue!(out, cursor::MoveTo(0, top as u16), SetForegroundColor(Color::DarkGrey),
+               Print("-".repeat(width)), ResetColor)?;
+        let selected = rows.get(index).and_then(|row| self.tree.get(&row.path).map(|node| (row, node)));
+        if let Some((row, node)) = selected {
+            let mut lines = vec![format!("{}  {} messages  {:.1}/s", row.path.join("/"), node.count(), node.rate())];
+            match node.topic {
+                Some(ref topic) => lines.extend(topic.history.iter().map(|payload| {
+                    format!("{} {} {}", timestamp(payload.received), if payload.retained { "R" } else { " " }, show(&payload.data))
+                })),
+                None => lines.push(format!("no messages on this topic, {} below it", node.topics()))
+            }
+            for (i, line) in lines.iter().take(details).enumerate() {
+                queue!(out, cursor::MoveTo(0, (top + 1 + i) as u16), Print(fit(line, width)))?;
+            }
+        } else {
+            queue!(out, cursor::MoveTo(0, (top + 1) as u16), Print(fit("waiting for messages", width)))?;
+        }
+        out.flush()
+    }
+}
+
+/// UTF-8 payloads on one line, the size of anything else
+fn show(payload: &[u8]) -> String {
+    match ::std::str::from_utf8(payload) {
+        Ok(text) => text.chars().map(|c| if c.is_control() { ' ' } else { c }).collect(),
+        Err(_) => format!("<{} bytes>", payload.len())
+    }
+}
+
+/// `text` cut to `width` characters
+fn fit(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+/// Wall clock time of day, UTC
+fn timestamp(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs() % 86400;
+    format!("{:02}:{:02}:{:02}.{:03}", secs / 3600, secs / 60 % 60, secs % 60, since.subsec_millis())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+    use crossterm::event::KeyCode;
+    use mqtt3::{Message, QoS, TopicPath};
+    use super::{Tree, View, RATE_WINDOW};
+
+    fn message(topic: &str, payload: &str, retain: bool) -> Message {
+        Message {
+            topic: TopicPath::from_str(topic).unwrap(),
+            qos: QoS::AtMostOnce,
+            retain: retain,
+            pid: None,
+            payload: Arc::new(payload.as_bytes().to_vec())
+        }
+    }
+
+    fn paths(tree: &Tree) -> Vec<String> {
+        tree.rows().iter().map(|row| row.path.join("/")).collect()
+    }
+
+    #[test]
+    fn explore_tree_test() {
+        let mut tree = Tree::new(2);
+        for (topic, payload) in [("sensors/temp", "21.5"), ("sensors/temp", "22.0"), ("sensors/temp", "22.5"),
+                                 ("sensors/hum", "40"), ("status", "ok")].iter() {
+            tree.insert(&message(topic, payload, *topic == "status"), UNIX_EPOCH);
+        }
+
+        // only the top level shows until something is expanded
+        assert_eq!(paths(&tree), vec!["sensors", "status"]);
+        let sensors = tree.get(&["sensors".to_string()]).unwrap();
+        assert_eq!((sensors.count(), sensors.topics()), (4, 2));
+
+        let temp = tree.get(&["sensors".to_string(), "temp".to_string()]).unwrap().topic.clone().unwrap();
+        assert_eq!(temp.count, 3);
+        let history: Vec<&[u8]> = temp.history.iter().map(|payload| &payload.data[..]).collect();
+        assert_eq!(history, vec![&b"22.5"[..], b"22.0"]);
+        assert!(tree.get(&["status".to_string()]).unwrap().topic.as_ref().unwrap().retained);
+
+        let later = Instant::now() + RATE_WINDOW + Duration::from_millis(100);
+        assert!(tree.tick(later));
+        assert!(tree.get(&["sensors".to_string()]).unwrap().rate() > 0.0);
+    }
+
+    #[test]
+    fn explore_keys_test() {
+        let mut view = View::new(10, String::new());
+        view.tree.insert(&message("a/b/c", "1", false), UNIX_EPOCH);
+        view.tree.insert(&message("d", "2", false), UNIX_EPOCH);
+
+        view.key(KeyCode::Right);
+        assert_eq!(paths(&view.tree), vec!["a", "a/b", "d"]);
+        view.key(KeyCode::Down);
+        view.key(KeyCode::Right);
+        assert_eq!(paths(&view.tree), vec!["a", "a/b", "a/b/c", "d"]);
+        view.key(KeyCode::Down);
+        assert_eq!(view.selected.clone().unwrap().join("/"), "a/b/c");
+
+        // back to the parent, then fold it
+        view.key(KeyCode::Left);
+        assert_eq!(view.selected.clone().unwrap().join("/"), "a/b");
+        view.key(KeyCode::Left);
+        assert_eq!(paths(&view.tree), vec!["a", "a/b", "d"]);
+        view.key(KeyCode::End);
+        assert_eq!(view.selected.clone().unwrap().join("/"), "d");
+    }
+}
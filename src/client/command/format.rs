@@ -0,0 +1,257 @@
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use mqtt3::Message;
+use serde_json;
+
+/// How `subscribe` prints received messages.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputFormat {
+    // coloured "topic payload" lines
+    Pretty,
+    // one JSON object per line
+    Json,
+    // payload bytes followed by a newline
+    Raw,
+    // header line and a hex dump of the payload
+    Hex,
+    Template(Template)
+}
+
+impl OutputFormat {
+    /// Parses `json`, `raw`, `hex`, `pretty` or a template with `{field}` placeholders
+    pub fn parse(format: &str) -> Result<OutputFormat, String> {
+        match format {
+            "pretty" => Ok(OutputFormat::Pretty),
+            "json" => Ok(OutputFormat::Json),
+            "raw" => Ok(OutputFormat::Raw),
+            "hex" => Ok(OutputFormat::Hex),
+            _ if format.contains('{') => Template::parse(format).map(OutputFormat::Template),
+            _ => Err(format!("unsupported output format {:?}", format))
+        }
+    }
+
+    /// Writes `message`, received at `received`. Not used for `Pretty`.
+    pub fn write<W: Write>(&self, out: &mut W, message: &Message, received: SystemTime) -> io::Result<()> {
+        match *self {
+            OutputFormat::Pretty => Ok(()),
+            OutputFormat::Json => writeln!(out, "{}", to_json(message, received)),
+            OutputFormat::Raw => {
+                out.write_all(&message.payload)?;
+                out.write_all(b"\n")
+            },
+            OutputFormat::Hex => {
+                writeln!(out, "{} qos={} retain={} len={}", message.topic.path, message.qos.to_u8(),
+                         message.retain, message.payload.len())?;
+                for (i, line) in message.payload.chunks(16).enumerate() {
+                    let ascii: String = line.iter().map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' }).collect();
+                    writeln!(out, "{:08x}  {:<47}  |{}|", i * 16, to_hex(line, " "), ascii)?;
+                }
+                Ok(())
+            },
+            OutputFormat::Template(ref template) => out.write_all(template.render(message, received).as_bytes())
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Text(String),
+    Field(Field)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Topic,
+    Payload,
+    Qos,
+    Retain,
+    Pid,
+    Len,
+    Timestamp,
+    Hex,
+    Base64
+}
+
+/// Output line like `{topic}\t{payload}`. `\t`, `\n` and `\\` are unescaped,
+/// `{{` and `}}` are literal braces and a newline ends every message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template(Vec<Piece>);
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, String> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('t') => text.push('\t'),
+                    Some('n') => text.push('\n'),
+                    Some('\\') | None => text.push('\\'),
+                    Some(other) => {
+                        text.push('\\');
+                        text.push(other);
+                    }
+                },
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                },
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed template field {{{}", name))
+                        }
+                    }
+                    let field = match name.as_str() {
+                        "topic" => Field::Topic,
+                        "payload" => Field::Payload,
+                        "qos" => Field::Qos,
+                        "retain" => Field::Retain,
+                        "pid" => Field::Pid,
+                        "len" => Field::Len,
+                        "timestamp" => Field::Timestamp,
+                        "hex" => Field::Hex,
+                        "base64" => Field::Base64,
+                        _ => return Err(format!("unknown template field {{{}}}", name))
+                    };
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(text.split_off(0)));
+                    }
+                    pieces.push(Piece::Field(field));
+                },
+                _ => text.push(c)
+            }
+        }
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        pieces.push(Piece::Text(text));
+        Ok(Template(pieces))
+    }
+
+    pub fn render(&self, message: &Message, received: SystemTime) -> String {
+        let mut out = String::new();
+        for piece in &self.0 {
+            match *piece {
+                Piece::Text(ref text) => out.push_str(text),
+                Piece::Field(field) => out.push_str(&match field {
+                    Field::Topic => message.topic.path.clone(),
+                    Field::Payload => String::from_utf8_lossy(&message.payload).into_owned(),
+                    Field::Qos => message.qos.to_u8().to_string(),
+                    Field::Retain => message.retain.to_string(),
+                    Field::Pid => message.pid.map_or(String::new(), |pid| pid.0.to_string()),
+                    Field::Len => message.payload.len().to_string(),
+                    Field::Timestamp => timestamp(received),
+                    Field::Hex => to_hex(&message.payload, ""),
+                    Field::Base64 => STANDARD.encode(message.payload.as_slice())
+                })
+            }
+        }
+        out
+    }
+}
+
+/// A message as `to_json` writes it, in this field order
+#[derive(Serialize)]
+struct JsonMessage<'a> {
+    topic: &'a str,
+    qos: u8,
+    retain: bool,
+    pid: Option<u16>,
+    payload: String,
+    encoding: &'static str,
+    // seconds since the Unix epoch, with milliseconds
+    timestamp: f64
+}
+
+/// `{"topic":..,"qos":..,"retain":..,"pid":..,"payload":..,"encoding":..,"timestamp":..}`,
+/// the payload as a string when it is UTF-8 and as base64 otherwise
+pub fn to_json(message: &Message, received: SystemTime) -> String {
+    let (payload, encoding) = match ::std::str::from_utf8(&message.payload) {
+        Ok(text) => (text.to_string(), "utf8"),
+        Err(_) => (STANDARD.encode(message.payload.as_slice()), "base64")
+    };
+    let since = received.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let json = JsonMessage {
+        topic: &message.topic.path,
+        qos: message.qos.to_u8(),
+        retain: message.retain,
+        pid: message.pid.map(|pid| pid.0),
+        payload: payload,
+        encoding: encoding,
+        timestamp: since.as_secs() as f64 + f64::from(since.subsec_millis()) / 1000.0
+    };
+    serde_json::to_string(&json).expect("a message is always valid JSON")
+}
+
+/// Seconds since the Unix epoch, with milliseconds
+fn timestamp(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:03}", since.as_secs(), since.subsec_millis())
+}
+
+pub fn to_hex(bytes: &[u8], separator: &str) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(separator)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+    use mqtt3::{Message, PacketIdentifier, QoS, TopicPath};
+    use super::{OutputFormat, to_json};
+
+    fn message(payload: &[u8]) -> Message {
+        Message {
+            topic: TopicPath::from_str("sensors/temp").unwrap(),
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            pid: Some(PacketIdentifier(7)),
+            payload: Arc::new(payload.to_vec())
+        }
+    }
+
+    fn render(format: &str, message: &Message) -> String {
+        let mut out = Vec::new();
+        let received = UNIX_EPOCH + Duration::from_millis(1500);
+        OutputFormat::parse(format).unwrap().write(&mut out, message, received).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json_format_test() {
+        let received = UNIX_EPOCH + Duration::from_millis(1500);
+        assert_eq!(to_json(&message(b"say \"hi\"\n"), received),
+                   "{\"topic\":\"sensors/temp\",\"qos\":1,\"retain\":true,\"pid\":7,\
+                    \"payload\":\"say \\\"hi\\\"\\n\",\"encoding\":\"utf8\",\"timestamp\":1.5}");
+        assert!(to_json(&message(&[0xff, 0x00, 0x10]), received).contains("\"payload\":\"/wAQ\",\"encoding\":\"base64\""));
+    }
+
+    #[test]
+    fn template_format_test() {
+        assert_eq!(render("{topic}\\t{payload}", &message(b"21.5")), "sensors/temp\t21.5\n");
+        assert_eq!(render("{{{qos}}} {pid} {len} {hex} {base64} {timestamp}", &message(b"ok")),
+                   "{1} 7 2 6f6b b2s= 1.500\n");
+        assert!(OutputFormat::parse("{nope}").is_err());
+        assert!(OutputFormat::parse("{topic\\t{payload}").is_err());
+        assert!(OutputFormat::parse("{topic").is_err());
+        assert!(OutputFormat::parse("xml").is_err());
+    }
+
+    #[test]
+    fn raw_and_hex_format_test() {
+        assert_eq!(render("raw", &message(b"21.5")), "21.5\n");
+        assert_eq!(render("hex", &message(b"hello")),
+                   format!("sensors/temp qos=1 retain=true len=5\n00000000  {:<47}  |hello|\n", "68 65 6c 6c 6f"));
+    }
+}
@@ -1,8 +1,21 @@
 pub mod publish;
 pub mod subscribe;
-
-pub use client::command::publish::PublishCommand;
+pub mod decode;
+pub mod bench;
+pub mod record;
+pub mod format;
+pub mod shell;
+pub mod explore;
+pub mod transfer;
+
+pub use client::command::publish::{PublishCommand, PublishInput};
 pub use client::command::subscribe::SubscribeCommand;
+pub use client::command::decode::{DecodeCommand, DecodeInput};
+pub use client::command::bench::BenchCommand;
+pub use client::command::record::{RecordCommand, ReplayCommand, BrokerOptions};
+pub use client::command::shell::ShellCommand;
+pub use client::command::explore::ExploreCommand;
+pub use client::command::format::OutputFormat;
 
 use std::collections::BTreeMap;
 use mqtt3::{PacketIdentifier, Message};
@@ -1,25 +1,48 @@
-use std::io::prelude::*;
-use std::fs::OpenOptions;
+use std::io::{self, prelude::*};
+use std::fs::{File, OpenOptions};
 use std::path::Path;
 use std::process::exit;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use openssl::ssl;
 use mqtt3::{QoS, Protocol};
-use netopt::{NetworkOptions, SslContext};
-use mqttc::{PubSub, ClientOptions, PubOpt};
+use netopt::{NetworkOptions, SslContext, Proxy};
+use mqttc::{PubSub, Client, ClientOptions, PubOpt, BrokerUrl};
 use super::{Command, LocalStorage};
+use super::transfer::Manifest;
 use client::logger::set_stdout_logger;
 
+/// Chunks sent before waiting for their acknowledgements
+const CHUNK_WINDOW: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PublishInput {
+    // empty payload
+    Empty,
+    Message(String),
+    File(String),
+    // all of stdin as one payload
+    Stdin,
+    // every stdin line as a message
+    StdinLines
+}
+
 #[derive(Debug, Clone)]
 pub struct PublishCommand {
     // Publish
     pub topic: String,
-    pub message: Option<String>,
-    pub file: Option<String>,
+    pub input: PublishInput,
     pub qos: QoS,
     pub retain: bool,
+    // messages per second in line mode
+    pub rate: Option<f64>,
+    // sends the file in chunks of this many bytes with a manifest
+    pub chunk_size: Option<usize>,
 
     // Connection
+    // takes the place of address and port
+    pub url: Option<BrokerUrl>,
     pub address: String,
     pub port: u16,
     pub keep_alive: u16,
@@ -34,18 +57,22 @@ pub struct PublishCommand {
     pub password: Option<String>,
 
     // SSL/TLS option
-    pub ssl_context: Option<ssl::SslContext>
+    pub tls: Option<SslContext>,
+
+    pub proxy: Option<Proxy>
 }
 
 impl Default for PublishCommand {
     fn default() -> PublishCommand {
         PublishCommand {
             topic: "nodefault".to_string(),
-            message: None,
-            file: None,
+            input: PublishInput::Empty,
             qos: QoS::AtLeastOnce,
             retain: false,
+            rate: None,
+            chunk_size: None,
 
+            url: None,
             address: "localhost".to_string(),
             port: 1883,
             keep_alive: 30,
@@ -57,7 +84,8 @@ impl Default for PublishCommand {
             username: None,
             password: None,
 
-            ssl_context: None
+            tls: None,
+            proxy: None
         }
     }
 }
@@ -71,9 +99,12 @@ impl Command for PublishCommand {
         debug!("{:?}", self);
         let mut netopt = NetworkOptions::new();
 
-        if let Some(ref ssl_context) = self.ssl_context {
-            let ssl = SslContext::new(ssl_context.clone());
-            netopt.tls(ssl);
+        if let Some(ref tls) = self.tls {
+            netopt.tls(tls.clone());
+        };
+
+        if let Some(ref proxy) = self.proxy {
+            netopt.proxy(proxy.clone());
         };
 
         let mut opts = ClientOptions::new();
@@ -94,31 +125,130 @@ impl Command for PublishCommand {
             opts.set_client_id(client_id.clone());
         };
 
-        let address = format!("{}:{}", self.address, self.port);
-        let mut client = opts.connect(address.as_str(), netopt).expect("Can't connect to server");
+        let mut client = match self.url {
+            Some(ref url) => opts.connect_url(url, netopt),
+            None => opts.connect(format!("{}:{}", self.address, self.port).as_str(), netopt)
+        }.expect("Can't connect to server");
+
+        match self.input {
+            PublishInput::Empty => {
+                client.publish(self.topic.clone(), "", PubOpt::new(self.qos, self.retain)).expect("Can't publish the message");
+            },
+            PublishInput::Message(ref message) => {
+                client.publish(self.topic.clone(), message.clone(), PubOpt::new(self.qos, self.retain)).expect("Can't publish the message");
+            },
+            PublishInput::File(ref file) if self.chunk_size.is_some() => {
+                self.publish_chunks(&mut client, file, self.chunk_size.unwrap());
+                exit(0);
+            },
+            PublishInput::File(ref file) => {
+                let path = Path::new(file);
+                if !path.exists() {
+                    panic!("File not found");
+                };
+
+                let mut payload = Vec::new();
+                let mut f = OpenOptions::new().read(true).open(file).expect("Can't open file");
+                f.read_to_end(&mut payload).expect("Can't read file");
+                println!("Sending file {} bytes...", payload.len());
+                client.publish(self.topic.clone(), payload, PubOpt::new(self.qos, self.retain)).expect("Can't publish the message");
+            },
+            PublishInput::Stdin => {
+                let mut payload = Vec::new();
+                io::stdin().read_to_end(&mut payload).expect("Can't read stdin");
+                client.publish(self.topic.clone(), payload, PubOpt::new(self.qos, self.retain)).expect("Can't publish the message");
+            },
+            PublishInput::StdinLines => {
+                self.publish_lines(&mut client);
+                exit(0);
+            }
+        }
 
-        if let Some(ref message) = self.message {
-            client.publish(self.topic.clone(), message.clone(), PubOpt::new(self.qos, self.retain)).expect("Can't publish the message");
-        } else if let Some(ref file) = self.file {
-            let path = Path::new(file);
-            if !path.exists() {
-                panic!("File not found");
+        self.wait_acks(&mut client);
+        exit(0);
+    }
+}
+
+impl PublishCommand {
+    /// Publishes stdin line by line over one connection, no faster than
+    /// `rate` and each after the previous one is acknowledged
+    fn publish_lines(&self, client: &mut Client) {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for line in stdin.lock().split(b'\n') {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        // ping while stdin is quiet, so the broker keeps the connection
+        let idle = Duration::from_secs(u64::from(self.keep_alive.max(2)) / 2);
+        let interval = self.rate.map(|rate| Duration::from_secs_f64(1.0 / rate));
+        let mut next = Instant::now();
+        loop {
+            let mut line = match rx.recv_timeout(idle) {
+                Ok(Ok(line)) => line,
+                Ok(Err(err)) => {
+                    println!("Can't read stdin: {}", err);
+                    exit(74); // input/output error
+                },
+                Err(RecvTimeoutError::Timeout) => {
+                    client.ping().expect("Can't ping the server");
+                    // until PINGRESP
+                    while client.await().unwrap().is_some() {};
+                    continue;
+                },
+                Err(RecvTimeoutError::Disconnected) => return
             };
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+
+            if let Some(interval) = interval {
+                let now = Instant::now();
+                if next > now {
+                    thread::sleep(next - now);
+                }
+                next = Instant::now().max(next) + interval;
+            }
+            client.publish(self.topic.clone(), line, PubOpt::new(self.qos, self.retain)).expect("Can't publish the message");
+            self.wait_acks(client);
+        }
+    }
 
-            let mut payload = Vec::new();
-            let mut f = OpenOptions::new().read(true).open(file).expect("Can't open file");
-            f.read_to_end(&mut payload).expect("Can't read file");
-            println!("Sending file {} bytes...", payload.len());
-            client.publish(self.topic.clone(), payload, PubOpt::new(self.qos, self.retain)).expect("Can't publish the message");
-        } else {
-            client.publish(self.topic.clone(), "", PubOpt::new(self.qos, self.retain)).expect("Can't publish the message");
+    /// Publishes the manifest of `file` and then its chunks at QoS 1,
+    /// waiting for acknowledgements whenever `CHUNK_WINDOW` are in flight
+    fn publish_chunks(&self, client: &mut Client, file: &str, chunk_size: usize) {
+        let manifest = File::open(file).and_then(|f| Manifest::read(file, chunk_size as u64, f)).expect("Can't read file");
+        println!("Sending file {} bytes in {} chunks as {}/{}...", manifest.size, manifest.chunks(), self.topic, manifest.id());
+        client.publish(manifest.manifest_topic(&self.topic), manifest.to_json(), PubOpt::new(QoS::AtLeastOnce, self.retain))
+            .expect("Can't publish the manifest");
+        let mut in_flight = 1;
+
+        let mut f = File::open(file).expect("Can't open file");
+        let mut chunk = vec![0; chunk_size];
+        for index in 0..manifest.chunks() {
+            let len = manifest.chunk_len(index) as usize;
+            f.read_exact(&mut chunk[..len]).expect("Can't read file");
+            client.publish(manifest.chunk_topic(&self.topic, index), chunk[..len].to_vec(), PubOpt::new(QoS::AtLeastOnce, false))
+                .expect("Can't publish the chunk");
+            in_flight += 1;
+            if in_flight >= CHUNK_WINDOW {
+                while client.await().unwrap().is_some() {};
+                in_flight = 0;
+            }
+        }
+        if in_flight > 0 {
+            while client.await().unwrap().is_some() {};
         }
+    }
 
+    fn wait_acks(&self, client: &mut Client) {
         if self.qos != QoS::AtMostOnce {
             // wait normalization
             while client.await().unwrap().is_some() {};
         }
-
-        exit(0);
     }
 }
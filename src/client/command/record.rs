@@ -0,0 +1,344 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, prelude::*, BufReader};
+use std::process::exit;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use mqtt3::{QoS, Protocol, SubscribeTopic};
+use netopt::{NetworkOptions, SslContext, Proxy};
+use mqttc::{PubSub, Client, ClientOptions, PubOpt, Error, BrokerUrl};
+use serde_json;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use super::{Command, LocalStorage};
+use super::format::to_json;
+use super::subscribe::{EXIT_DISCONNECTED, EXIT_PROTOCOL};
+use client::logger::set_stdout_logger;
+
+/// Broker connection settings shared by `record` and `replay`
+#[derive(Debug, Clone)]
+pub struct BrokerOptions {
+    // takes the place of address and port
+    pub url: Option<BrokerUrl>,
+    pub address: String,
+    pub port: u16,
+    pub keep_alive: u16,
+    pub protocol: Protocol,
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: Option<SslContext>,
+    pub proxy: Option<Proxy>
+}
+
+impl Default for BrokerOptions {
+    fn default() -> BrokerOptions {
+        BrokerOptions {
+            url: None,
+            address: "localhost".to_string(),
+            port: 1883,
+            keep_alive: 30,
+            protocol: Protocol::MQTT(4),
+            client_id: None,
+            username: None,
+            password: None,
+            tls: None,
+            proxy: None
+        }
+    }
+}
+
+impl BrokerOptions {
+    // `SslContext` is uninhabited without a TLS backend
+    #[cfg_attr(not(any(feature = "ssl", feature = "rustls")), allow(unreachable_code))]
+    pub fn connect(&self) -> Result<Client, Error> {
+        let mut netopt = NetworkOptions::new();
+        if let Some(ref tls) = self.tls {
+            netopt.tls(tls.clone());
+        };
+        if let Some(ref proxy) = self.proxy {
+            netopt.proxy(proxy.clone());
+        };
+
+        let mut opts = ClientOptions::new();
+        opts.set_protocol(self.protocol);
+        opts.set_keep_alive(self.keep_alive);
+        opts.set_clean_session(true);
+        opts.set_outgoing_store(LocalStorage::new());
+        opts.set_incomming_store(LocalStorage::new());
+        if let Some(ref client_id) = self.client_id {
+            opts.set_client_id(client_id.clone());
+        };
+        if let Some(ref username) = self.username {
+            opts.set_username(username.clone());
+        };
+        if let Some(ref password) = self.password {
+            opts.set_password(password.clone());
+        };
+
+        match self.url {
+            Some(ref url) => opts.connect_url(url, netopt),
+            None => opts.connect(format!("{}:{}", self.address, self.port).as_str(), netopt)
+        }
+    }
+
+    /// The URL, or `address:port`
+    pub fn target(&self) -> String {
+        match self.url {
+            Some(ref url) => url.to_string(),
+            None => format!("{}:{}", self.address, self.port)
+        }
+    }
+}
+
+/// Writes received messages as JSON lines, the `subscribe --format json` layout.
+#[derive(Debug, Clone)]
+pub struct RecordCommand {
+    pub topics: Vec<SubscribeTopic>,
+    // `-` for stdout
+    pub output: String,
+    pub limit: Option<u32>,
+    pub connection: BrokerOptions,
+    pub debug: bool
+}
+
+impl Default for RecordCommand {
+    fn default() -> RecordCommand {
+        RecordCommand {
+            topics: vec![SubscribeTopic { topic_path: "#".to_string(), qos: QoS::ExactlyOnce }],
+            output: "-".to_string(),
+            limit: None,
+            connection: BrokerOptions::default(),
+            debug: false
+        }
+    }
+}
+
+impl Command for RecordCommand {
+    fn run(&self) -> ! {
+        if self.debug {
+            set_stdout_logger().unwrap();
+        }
+
+        debug!("{:?}", self);
+        let mut output: Box<dyn Write> = if self.output == "-" {
+            Box::new(io::stdout())
+        } else {
+            match OpenOptions::new().create(true).append(true).open(&self.output) {
+                Ok(file) => Box::new(file),
+                Err(err) => {
+                    eprintln!("Can't open {}: {}", self.output, err);
+                    exit(73); // can't create output file
+                }
+            }
+        };
+
+        let mut client = self.connection.connect().expect("Can't connect to server");
+        client.subscribe(self.topics.clone()).expect("Can't subscribe");
+
+        let mut recorded = 0;
+        loop {
+            match client.await() {
+                Ok(Some(message)) => {
+                    if message.qos == QoS::ExactlyOnce {
+                        let _ = client.complete(message.pid.unwrap());
+                    }
+                    let line = to_json(&message, SystemTime::now());
+                    if let Err(err) = writeln!(output, "{}", line).and_then(|_| output.flush()) {
+                        eprintln!("Can't write record: {}", err);
+                        exit(74); // input/output error
+                    }
+                    recorded += 1;
+                    if self.limit == Some(recorded) {
+                        let _ = client.disconnect();
+                        exit(0);
+                    }
+                },
+                Ok(None) => (),
+                Err(Error::Disconnected) | Err(Error::ConnectionAbort) => exit(EXIT_DISCONNECTED),
+                Err(err) => {
+                    // stdout may be the recording
+                    eprintln!("{:?}", err);
+                    client.terminate();
+                    exit(EXIT_PROTOCOL);
+                }
+            }
+        }
+    }
+}
+
+/// Publishes a recording again, keeping its timing unless `speed` is `None`.
+#[derive(Debug, Clone)]
+pub struct ReplayCommand {
+    // `-` for stdin
+    pub input: String,
+    // 2.0 replays twice as fast, `None` as fast as possible
+    pub speed: Option<f64>,
+    // topics starting with the first prefix get the second one instead
+    pub rewrite: Option<(String, String)>,
+    pub connection: BrokerOptions,
+    pub debug: bool
+}
+
+impl Default for ReplayCommand {
+    fn default() -> ReplayCommand {
+        ReplayCommand {
+            input: "-".to_string(),
+            speed: Some(1.0),
+            rewrite: None,
+            connection: BrokerOptions::default(),
+            debug: false
+        }
+    }
+}
+
+impl Command for ReplayCommand {
+    fn run(&self) -> ! {
+        if self.debug {
+            set_stdout_logger().unwrap();
+        }
+
+        debug!("{:?}", self);
+        let input: Box<dyn BufRead> = if self.input == "-" {
+            Box::new(BufReader::new(io::stdin()))
+        } else {
+            match File::open(&self.input) {
+                Ok(file) => Box::new(BufReader::new(file)),
+                Err(err) => {
+                    println!("Can't open {}: {}", self.input, err);
+                    exit(66); // cannot open input
+                }
+            }
+        };
+
+        let mut client = self.connection.connect().expect("Can't connect to server");
+        let mut first: Option<(f64, Instant)> = None;
+        for (n, line) in input.lines().enumerate() {
+            let line = line.unwrap_or_else(|err| {
+                println!("Can't read line {}: {}", n + 1, err);
+                exit(74); // input/output error
+            });
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = Record::parse(&line).unwrap_or_else(|err| {
+                println!("line {}: {}", n + 1, err);
+                exit(65); // data format error
+            });
+
+            if let Some(speed) = self.speed {
+                let (timestamp, start) = *first.get_or_insert((record.timestamp, Instant::now()));
+                let due = Duration::from_secs_f64(((record.timestamp - timestamp) / speed).max(0.0));
+                let elapsed = start.elapsed();
+                if due > elapsed {
+                    thread::sleep(due - elapsed);
+                }
+            }
+
+            let topic = self.rewrite_topic(&record.topic);
+            client.publish(topic, record.payload, PubOpt::new(record.qos, record.retain)).expect("Can't publish the message");
+            if record.qos != QoS::AtMostOnce {
+                while client.await().unwrap().is_some() {};
+            }
+        }
+
+        let _ = client.disconnect();
+        exit(0);
+    }
+}
+
+impl ReplayCommand {
+    pub fn rewrite_topic(&self, topic: &str) -> String {
+        match self.rewrite {
+            Some((ref from, ref to)) if topic.starts_with(from.as_str()) => format!("{}{}", to, &topic[from.len()..]),
+            _ => topic.to_string()
+        }
+    }
+}
+
+/// One recorded message
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub topic: String,
+    pub qos: QoS,
+    pub retain: bool,
+    pub payload: Vec<u8>,
+    // seconds since the Unix epoch
+    pub timestamp: f64
+}
+
+/// A line as `record` writes it, `pid` is left out
+#[derive(Deserialize)]
+struct RawRecord {
+    topic: String,
+    qos: u8,
+    #[serde(default)]
+    retain: bool,
+    payload: String,
+    // `utf8` when missing
+    encoding: Option<String>,
+    timestamp: f64
+}
+
+impl Record {
+    /// Reads a line written by `record`
+    pub fn parse(line: &str) -> Result<Record, String> {
+        let raw: RawRecord = serde_json::from_str(line).map_err(|err| err.to_string())?;
+        let payload = match raw.encoding.as_deref() {
+            Some("base64") => STANDARD.decode(&raw.payload).map_err(|err| format!("invalid base64 payload: {}", err))?,
+            Some("utf8") | None => raw.payload.into_bytes(),
+            Some(encoding) => return Err(format!("unknown encoding {:?}", encoding))
+        };
+        Ok(Record {
+            topic: raw.topic,
+            qos: QoS::from_u8(raw.qos).map_err(|_| "invalid qos".to_string())?,
+            retain: raw.retain,
+            payload: payload,
+            timestamp: raw.timestamp
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+    use mqtt3::{Message, QoS, TopicPath};
+    use super::super::format::to_json;
+    use super::{Record, ReplayCommand};
+
+    #[test]
+    fn record_roundtrip_test() {
+        for payload in [&b"say \"hi\"\n\t\x01"[..], &[0xff, 0x00, 0x10]].iter() {
+            let message = Message {
+                topic: TopicPath::from_str("sensors/temp").unwrap(),
+                qos: QoS::ExactlyOnce,
+                retain: true,
+                pid: None,
+                payload: Arc::new(payload.to_vec())
+            };
+            let line = to_json(&message, UNIX_EPOCH + Duration::from_millis(1500));
+            assert_eq!(Record::parse(&line).unwrap(), Record {
+                topic: "sensors/temp".to_string(),
+                qos: QoS::ExactlyOnce,
+                retain: true,
+                payload: payload.to_vec(),
+                timestamp: 1.5
+            });
+        }
+
+        let record = Record::parse(r#"{"topic": "café/😀", "qos": 0, "payload": "", "timestamp": 2}"#).unwrap();
+        assert_eq!(record.topic, "café/😀");
+        assert!(!record.retain);
+        assert!(Record::parse(r#"{"topic":"a","qos":0}"#).is_err());
+        assert!(Record::parse(r#"{"topic":"a","qos":0,"payload":"","timestamp":1} x"#).is_err());
+    }
+
+    #[test]
+    fn replay_rewrite_test() {
+        let mut command = ReplayCommand::default();
+        assert_eq!(command.rewrite_topic("prod/sensors/temp"), "prod/sensors/temp");
+        command.rewrite = Some(("prod/".to_string(), "staging/".to_string()));
+        assert_eq!(command.rewrite_topic("prod/sensors/temp"), "staging/sensors/temp");
+        assert_eq!(command.rewrite_topic("other/prod/temp"), "other/prod/temp");
+    }
+}
@@ -0,0 +1,515 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Sender, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+use rustyline::{CompletionType, Config, Context, Editor, ExternalPrinter, Helper};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use term::{self, Terminal};
+use term::terminfo::TerminfoTerminal;
+use mqtt3::{Message, QoS, SubscribeTopic};
+use mqttc::{PubSub, Client, ClientState, PubOpt, Error};
+use super::record::BrokerOptions;
+use super::Command;
+use client::logger::set_stdout_logger;
+
+const COMMANDS: &[&str] = &["sub", "unsub", "pub", "subs", "status", "reconnect", "help", "quit"];
+
+const HELP: &str = "\
+sub <filter> [qos]                    Subscribe, QoS 2 by default
+unsub <filter>                        Unsubscribe
+pub <topic> <payload> [qos] [retain]  Publish, QoS 1 by default
+subs                                  List granted subscriptions
+status                                Show the connection state
+reconnect                             Drop the connection and connect again
+help                                  Show this message
+quit                                  Disconnect and leave, as does Ctrl-D
+Quote arguments with spaces, e.g. pub cmd/led \"on 50%\" 1 retain";
+
+/// Topics kept for completion, so a busy `#` subscription can't grow it forever
+const MAX_TOPICS: usize = 10000;
+
+/// How often the connection is checked for messages between commands
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Interactive session on a single connection.
+#[derive(Debug, Clone, Default)]
+pub struct ShellCommand {
+    pub connection: BrokerOptions,
+    // `$XDG_STATE_HOME/mqttc/history` when not set
+    pub history: Option<PathBuf>,
+    pub debug: bool
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Request {
+    Subscribe(String, QoS),
+    Unsubscribe(String),
+    Publish(String, Vec<u8>, QoS, bool),
+    Subscriptions,
+    Status,
+    Reconnect,
+    Help,
+    Quit
+}
+
+/// What the connection thread answers, printed as a titled line
+type Reply = Result<(&'static str, String), String>;
+
+/// Topics seen in messages and commands, for tab completion
+type Topics = Arc<Mutex<BTreeSet<String>>>;
+
+impl Command for ShellCommand {
+    fn run(&self) -> ! {
+        if self.debug {
+            set_stdout_logger().unwrap();
+        }
+
+        debug!("{:?}", self);
+        let client = self.connection.connect().expect("Can't connect to server");
+        let topics: Topics = Arc::new(Mutex::new(BTreeSet::new()));
+
+        // Tab completes the common prefix and lists the choices like a shell
+        let config = Config::builder().completion_type(CompletionType::List).build();
+        let mut editor: Editor<ShellHelper, DefaultHistory> = Editor::with_config(config).expect("Can't set up the terminal");
+        editor.set_helper(Some(ShellHelper { topics: topics.clone() }));
+        let history = self.history.clone().or_else(default_history);
+        if let Some(ref history) = history {
+            let _ = editor.load_history(history);
+        }
+
+        // messages are written above the prompt while a line is edited. The
+        // printer can block until the prompt is back, so it gets a thread of
+        // its own rather than holding up the connection.
+        let (print, lines) = mpsc::channel::<String>();
+        match editor.create_external_printer() {
+            Ok(mut printer) => thread::spawn(move || for line in lines {
+                let _ = printer.print(line + "\n");
+            }),
+            Err(_) => thread::spawn(move || for line in lines {
+                println!("{}", line);
+            })
+        };
+        let (requests, incoming) = mpsc::channel();
+        let session = Session {
+            client: client,
+            target: self.connection.target(),
+            topics: topics,
+            print: print,
+            connected: Instant::now(),
+            received: 0,
+            published: 0
+        };
+        let connection = thread::spawn(move || session.run(incoming));
+
+        println!("{}", titled("Connected", &self.connection.target(), term::color::BRIGHT_GREEN));
+        println!("Type help for the commands");
+        loop {
+            let line = match editor.readline("mqttc> ") {
+                Ok(line) => line,
+                // Ctrl-C drops the line like a shell does
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => "quit".to_string(),
+                Err(err) => {
+                    println!("{}", titled("Error", &err.to_string(), term::color::BRIGHT_RED));
+                    "quit".to_string()
+                }
+            };
+            if !line.trim().is_empty() {
+                let _ = editor.add_history_entry(line.as_str());
+            }
+
+            let request = match parse(&line) {
+                Ok(Some(Request::Help)) => {
+                    println!("{}", HELP);
+                    continue;
+                },
+                Ok(Some(request)) => request,
+                Ok(None) => continue,
+                Err(err) => {
+                    println!("{}", titled("Error", &err, term::color::BRIGHT_RED));
+                    continue;
+                }
+            };
+            let quit = request == Request::Quit;
+            let (reply, answer) = mpsc::channel();
+            if requests.send((request, reply)).is_err() {
+                break;
+            }
+            match answer.recv() {
+                Ok(Ok((title, message))) => println!("{}", titled(title, &message, term::color::BRIGHT_GREEN)),
+                Ok(Err(err)) => println!("{}", titled("Error", &err, term::color::BRIGHT_RED)),
+                Err(_) => break
+            }
+            if quit {
+                break;
+            }
+        }
+
+        if let Some(ref history) = history {
+            if let Some(dir) = history.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            let _ = editor.save_history(history);
+        }
+        drop(requests);
+        let _ = connection.join();
+        exit(0);
+    }
+}
+
+/// The connection, owned by its own thread so messages arrive while a
+/// command is typed
+struct Session {
+    client: Client,
+    target: String,
+    topics: Topics,
+    print: Sender<String>,
+    connected: Instant,
+    received: u64,
+    published: u64
+}
+
+impl Session {
+    fn run(mut self, requests: Receiver<(Request, Sender<Reply>)>) {
+        let mut online = true;
+        loop {
+            let next = if online {
+                requests.try_recv().map_err(|err| err == TryRecvError::Disconnected)
+            } else {
+                // nothing to poll until `reconnect`
+                requests.recv_timeout(POLL_INTERVAL).map_err(|err| err == RecvTimeoutError::Disconnected)
+            };
+            match next {
+                Ok((Request::Quit, reply)) => {
+                    let _ = self.client.disconnect();
+                    let _ = reply.send(Ok(("Disconnected", self.target.clone())));
+                    return;
+                },
+                Ok((request, reply)) => {
+                    let answer = self.handle(request);
+                    online = self.client.state() == ClientState::Connected;
+                    let _ = reply.send(answer);
+                },
+                Err(true) => return,
+                Err(false) => ()
+            }
+
+            if online {
+                match self.client.poll(POLL_INTERVAL) {
+                    Ok(Some(message)) => self.show(&message),
+                    Ok(None) => (),
+                    Err(err) => {
+                        online = false;
+                        let _ = self.print.send(titled("Disconnected", &format!("{}, type reconnect to connect again", describe(&err)),
+                                                       term::color::BRIGHT_RED));
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, request: Request) -> Reply {
+        match request {
+            Request::Subscribe(filter, qos) => {
+                self.client.subscribe(SubscribeTopic { topic_path: filter.clone(), qos: qos }).map_err(|err| describe(&err))?;
+                self.settle(true)?;
+                self.remember(&filter);
+                match self.client.subscriptions().iter().find(|sub| sub.topic_path.path == filter) {
+                    Some(sub) => Ok(("Subscribed", format!("{} qos {}", filter, sub.qos.to_u8()))),
+                    None => Err(format!("the broker refused {}", filter))
+                }
+            },
+            Request::Unsubscribe(filter) => {
+                self.client.unsubscribe(vec![filter.clone()]).map_err(|err| describe(&err))?;
+                self.settle(true)?;
+                Ok(("Unsubscribed", filter))
+            },
+            Request::Publish(topic, payload, qos, retain) => {
+                let len = payload.len();
+                self.client.publish(topic.clone(), payload, PubOpt::new(qos, retain)).map_err(|err| describe(&err))?;
+                self.settle(qos != QoS::AtMostOnce)?;
+                self.published += 1;
+                self.remember(&topic);
+                Ok(("Published", format!("{} ({} bytes)", topic, len)))
+            },
+            Request::Subscriptions => {
+                let subscriptions = self.client.subscriptions();
+                if subscriptions.is_empty() {
+                    return Ok(("Subscriptions", "none".to_string()));
+                }
+                let list: Vec<String> = subscriptions.iter()
+                    .map(|sub| format!("{} qos {}", sub.topic_path.path, sub.qos.to_u8()))
+                    .collect();
+                Ok(("Subscriptions", list.join(", ")))
+            },
+            Request::Status => {
+                let state = match self.client.state() {
+                    ClientState::Connected => format!("connected for {}s", self.connected.elapsed().as_secs()),
+                    ClientState::Handshake => "connecting".to_string(),
+                    ClientState::Disconnected => "disconnected".to_string()
+                };
+                Ok(("Status", format!("{} to {}, session present {}, {} subscriptions, {} received, {} published",
+                                      state, self.target, self.client.session_present(),
+                                      self.client.subscriptions().len(), self.received, self.published)))
+            },
+            Request::Reconnect => {
+                self.client.terminate();
+                self.client.reconnect().map_err(|err| describe(&err))?;
+                self.connected = Instant::now();
+                // granted subscriptions are sent again
+                let resubscribed = !self.client.subscriptions().is_empty();
+                self.settle(resubscribed)?;
+                Ok(("Connected", self.target.clone()))
+            },
+            Request::Help | Request::Quit => unreachable!()
+        }
+    }
+
+    /// Waits for the acknowledgements of the last command, showing the
+    /// messages that arrive meanwhile. `await` blocks until the keep-alive
+    /// runs out when nothing is in flight, so then only poll briefly.
+    fn settle(&mut self, in_flight: bool) -> Result<(), String> {
+        if !in_flight {
+            if let Some(message) = self.client.poll(POLL_INTERVAL).map_err(|err| describe(&err))? {
+                self.show(&message);
+            }
+            return Ok(());
+        }
+        loop {
+            match self.client.await() {
+                Ok(Some(message)) => self.show(&message),
+                Ok(None) => return Ok(()),
+                Err(err) => return Err(describe(&err))
+            }
+        }
+    }
+
+    fn show(&mut self, message: &Message) {
+        if message.qos == QoS::ExactlyOnce {
+            let _ = self.client.complete(message.pid.unwrap());
+        }
+        self.received += 1;
+        self.remember(&message.topic.path);
+
+        let color = match message.qos {
+            QoS::AtMostOnce => term::color::BRIGHT_CYAN,
+            QoS::AtLeastOnce => term::color::BRIGHT_MAGENTA,
+            QoS::ExactlyOnce => term::color::BRIGHT_BLUE
+        };
+        let payload = match ::std::str::from_utf8(&message.payload) {
+            Ok(payload) => payload.to_string(),
+            Err(_) => format!("payload did not contain valid UTF-8 ({} bytes)", message.payload.len())
+        };
+        let retained = if message.retain { " (retained)" } else { "" };
+        let _ = self.print.send(titled(&message.topic.path, &format!("{}{}", payload, retained), color));
+    }
+
+    fn remember(&self, topic: &str) {
+        let mut topics = self.topics.lock().unwrap();
+        if topics.len() < MAX_TOPICS {
+            topics.insert(topic.to_string());
+        }
+    }
+}
+
+fn describe(err: &Error) -> String {
+    match *err {
+        Error::Disconnected | Error::ConnectionAbort => "connection closed".to_string(),
+        ref err => err.to_string()
+    }
+}
+
+/// `title message` laid out like `subscribe` prints it, coloured when the
+/// terminal supports it
+fn titled(title: &str, message: &str, color: u16) -> String {
+    let mut t = match TerminfoTerminal::new(Vec::new()) {
+        Some(t) => t,
+        None => return format!("{:>14} {}", title, message)
+    };
+    let _ = t.fg(color);
+    let _ = write!(t, "{:>14} ", title);
+    let _ = t.reset();
+    let _ = write!(t, "{}", message);
+    String::from_utf8_lossy(&t.into_inner()).into_owned()
+}
+
+/// `$XDG_STATE_HOME/mqttc/history`, falling back to `~/.local/state`
+fn default_history() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_STATE_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))?;
+    Some(dir.join("mqttc").join("history"))
+}
+
+fn parse(line: &str) -> Result<Option<Request>, String> {
+    let words = split(line)?;
+    let (command, args) = match words.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => return Ok(None)
+    };
+    let usage = |usage: &str| format!("usage: {}", usage);
+    let request = match (command, args.len()) {
+        ("sub", 1) | ("sub", 2) => Request::Subscribe(args[0].clone(), match args.get(1) {
+            Some(qos) => parse_qos(qos)?,
+            None => QoS::ExactlyOnce
+        }),
+        ("sub", _) => return Err(usage("sub <filter> [qos]")),
+        ("unsub", 1) => Request::Unsubscribe(args[0].clone()),
+        ("unsub", _) => return Err(usage("unsub <filter>")),
+        ("pub", 2..=4) => {
+            let qos = match args.get(2) {
+                Some(qos) => parse_qos(qos)?,
+                None => QoS::AtLeastOnce
+            };
+            let retain = match args.get(3).map(|s| s.as_str()) {
+                Some("retain") | Some("r") | Some("true") => true,
+                Some("false") | None => false,
+                Some(_) => return Err(usage("pub <topic> <payload> [qos] [retain]"))
+            };
+            Request::Publish(args[0].clone(), args[1].clone().into_bytes(), qos, retain)
+        },
+        ("pub", _) => return Err(usage("pub <topic> <payload> [qos] [retain]")),
+        ("subs", 0) => Request::Subscriptions,
+        ("status", 0) => Request::Status,
+        ("reconnect", 0) => Request::Reconnect,
+        ("quit", 0) | ("exit", 0) => Request::Quit,
+        ("help", _) => Request::Help,
+        (command, _) if COMMANDS.contains(&command) => return Err(format!("{} takes no arguments", command)),
+        (command, _) => return Err(format!("unknown command {:?}, type help for the list", command))
+    };
+    Ok(Some(request))
+}
+
+fn parse_qos(qos: &str) -> Result<QoS, String> {
+    qos.parse::<u8>().ok().and_then(|qos| QoS::from_u8(qos).ok())
+        .ok_or_else(|| format!("unsupported qos value {:?}", qos))
+}
+
+/// Splits at whitespace, keeping quoted text together. Backslash escapes
+/// the next character outside single quotes.
+fn split(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            },
+            '"' | '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some('\\') if c == '"' => word.push(chars.next().ok_or("unfinished escape")?),
+                        Some(other) => word.push(other),
+                        None => return Err("unclosed quote".to_string())
+                    }
+                }
+            },
+            '\\' => word.get_or_insert_with(String::new).push(chars.next().ok_or("unfinished escape")?),
+            c => word.get_or_insert_with(String::new).push(c)
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Completes a topic one level at a time, `se` becomes `sensors/` before
+/// `sensors/temp`
+fn complete_topic(topics: &BTreeSet<String>, partial: &str) -> Vec<String> {
+    let mut candidates: Vec<String> = topics.range(partial.to_string()..)
+        .take_while(|topic| topic.starts_with(partial))
+        .map(|topic| match topic[partial.len()..].find('/') {
+            Some(i) => topic[..partial.len() + i + 1].to_string(),
+            None => topic.clone()
+        })
+        .collect();
+    candidates.dedup();
+    candidates
+}
+
+struct ShellHelper {
+    topics: Topics
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let partial = &line[start..pos];
+        let before: Vec<&str> = line[..start].split_whitespace().collect();
+        let candidates = match before.as_slice() {
+            [] => COMMANDS.iter().filter(|command| command.starts_with(partial)).map(|command| command.to_string()).collect(),
+            ["sub"] | ["unsub"] | ["pub"] => complete_topic(&self.topics.lock().unwrap(), partial),
+            _ => Vec::new()
+        };
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _: bool) -> Cow<'b, str> {
+        Cow::Borrowed(prompt)
+    }
+}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+    use mqtt3::QoS;
+    use super::{parse, split, complete_topic, Request};
+
+    #[test]
+    fn shell_parse_test() {
+        assert_eq!(split("pub cmd/led \"on 50%\" 1 'a \"b\"' x\\ y").unwrap(),
+                   vec!["pub", "cmd/led", "on 50%", "1", "a \"b\"", "x y"]);
+        assert!(split("pub a \"open").is_err());
+
+        assert_eq!(parse("  ").unwrap(), None);
+        assert_eq!(parse("sub sensors/#").unwrap(), Some(Request::Subscribe("sensors/#".to_string(), QoS::ExactlyOnce)));
+        assert_eq!(parse("sub sensors/# 0").unwrap(), Some(Request::Subscribe("sensors/#".to_string(), QoS::AtMostOnce)));
+        assert_eq!(parse("pub cmd/led \"on 50%\" 2 retain").unwrap(),
+                   Some(Request::Publish("cmd/led".to_string(), b"on 50%".to_vec(), QoS::ExactlyOnce, true)));
+        assert_eq!(parse("pub cmd/led on").unwrap(),
+                   Some(Request::Publish("cmd/led".to_string(), b"on".to_vec(), QoS::AtLeastOnce, false)));
+        assert_eq!(parse("status").unwrap(), Some(Request::Status));
+        assert!(parse("sub").is_err());
+        assert!(parse("sub a 3").is_err());
+        assert!(parse("pub a b 1 maybe").is_err());
+        assert!(parse("status now").is_err());
+        assert!(parse("publish a b").is_err());
+    }
+
+    #[test]
+    fn shell_complete_test() {
+        let topics: BTreeSet<String> = ["sensors/temp", "sensors/humidity", "sensors", "status", "cmd/led"]
+            .iter().map(|topic| topic.to_string()).collect();
+        assert_eq!(complete_topic(&topics, "se"), vec!["sensors", "sensors/"]);
+        assert_eq!(complete_topic(&topics, "sensors/"), vec!["sensors/humidity", "sensors/temp"]);
+        assert_eq!(complete_topic(&topics, "s"), vec!["sensors", "sensors/", "status"]);
+        assert!(complete_topic(&topics, "x").is_empty());
+    }
+}
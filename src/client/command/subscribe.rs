@@ -1,21 +1,34 @@
-use std::io::prelude::*;
+use std::io::{self, prelude::*};
+use std::fs::{File, OpenOptions};
 use term;
-use openssl::ssl;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 use std::process::exit;
-use mqtt3::{self, LastWill, SubscribeTopic, QoS, Protocol};
-use netopt::{NetworkOptions, SslContext};
+use std::path::PathBuf;
+use mqtt3::{self, LastWill, SubscribeTopic, QoS, Protocol, Message};
+use netopt::{NetworkOptions, SslContext, Proxy};
 use mqttc::store;
-use mqttc::{PubSub, ClientOptions, ReconnectMethod, Error};
-use super::{Command, LocalStorage};
+use mqttc::{PubSub, Client, ClientOptions, ReconnectMethod, Error, BrokerUrl};
+use super::{Command, LocalStorage, OutputFormat};
+use super::transfer::{Assembler, Progress};
 use client::logger::set_stdout_logger;
 
+/// Exit status once `limit` messages have been shown
+pub const EXIT_LIMIT: i32 = 0;
+/// Exit status when the connection is lost and not reestablished
+pub const EXIT_DISCONNECTED: i32 = 69;
+/// Exit status when no message arrives within `timeout`
+pub const EXIT_TIMEOUT: i32 = 75;
+/// Exit status on protocol and other client errors
+pub const EXIT_PROTOCOL: i32 = 76;
+
 #[derive(Debug, Clone)]
 pub struct SubscribeCommand {
     // Subscribe
     pub topics: Vec<SubscribeTopic>,
 
     // Connection
+    // takes the place of address and port
+    pub url: Option<BrokerUrl>,
     pub address: String,
     pub port: u16,
     pub clean_session: bool,
@@ -28,6 +41,9 @@ pub struct SubscribeCommand {
     pub reconnect: bool,
     pub protocol: Protocol,
     pub silence: bool,
+    pub format: OutputFormat,
+    // reassembles chunked file transfers here instead of printing messages
+    pub save_dir: Option<PathBuf>,
 
     // Authorization
     pub client_id: Option<String>,
@@ -36,17 +52,21 @@ pub struct SubscribeCommand {
 
     // Filters
     pub limit: Option<u32>,
+    pub timeout: Option<Duration>,
     pub retain: bool,
     pub topic_filters: Vec<String>,
 
     // SSL/TLS option
-    pub ssl_context: Option<ssl::SslContext>,
+    pub tls: Option<SslContext>,
+
+    pub proxy: Option<Proxy>
 }
 
 impl Default for SubscribeCommand {
     fn default() -> SubscribeCommand {
         SubscribeCommand {
             topics: vec![SubscribeTopic { topic_path: "#".to_string(), qos: QoS::ExactlyOnce }],
+            url: None,
             address: "localhost".to_string(),
             port: 1883,
             clean_session: true,
@@ -57,13 +77,17 @@ impl Default for SubscribeCommand {
             reconnect: false,
             protocol: Protocol::MQTT(4),
             silence: false,
+            format: OutputFormat::Pretty,
+            save_dir: None,
             client_id: None,
             username: None,
             password: None,
             limit: None,
-            retain: false,
+            timeout: None,
+            retain: true,
             topic_filters: Vec::new(),
-            ssl_context: None
+            tls: None,
+            proxy: None
         }
     }
 }
@@ -77,10 +101,12 @@ impl Command for SubscribeCommand {
         debug!("{:?}", self);
         let mut netopt = NetworkOptions::new();
 
-        if let Some(ref ssl_context) = self.ssl_context {
-            let ssl = SslContext::new(ssl_context.clone());
-            netopt.tls(ssl);
-            //print_message("TLS", ssl_context., term::color::BRIGHT_GREEN );
+        if let Some(ref tls) = self.tls {
+            netopt.tls(tls.clone());
+        };
+
+        if let Some(ref proxy) = self.proxy {
+            netopt.proxy(proxy.clone());
         };
 
         let mut opts = ClientOptions::new();
@@ -106,30 +132,110 @@ impl Command for SubscribeCommand {
             opts.set_client_id(client_id.clone());
         };
 
-        if !self.debug && !self.silence {
+        // only messages go to stdout unless they are printed for people
+        let verbose = !self.debug && !self.silence && self.format == OutputFormat::Pretty;
+
+        if verbose {
             print_legend();
         };
 
-        let address = format!("{}:{}", self.address, self.port);
+        let address = match self.url {
+            Some(ref url) => url.to_string(),
+            None => format!("{}:{}", self.address, self.port)
+        };
 
-        if !self.debug && !self.silence {
+        if verbose {
             print_message("Connecting to", address.as_str(), term::color::BRIGHT_GREEN);
         };
 
-        let mut client = opts.connect(address.as_str(), netopt).expect("Can't connect to server");
+        let mut client = match self.url {
+            Some(ref url) => opts.connect_url(url, netopt),
+            None => opts.connect(address.as_str(), netopt)
+        }.expect("Can't connect to server");
 
-        if !self.debug && !self.silence {
+        if verbose {
             print_topics(&self.topics);
         };
 
+        let mut log = self.log_file.as_ref().map(|path| {
+            OpenOptions::new().create(true).append(true).open(path).unwrap_or_else(|err| {
+                print_error(format!("Can't open log file {}: {}", path, err));
+                exit(73); // can't create output file
+            })
+        });
+
+        let mut assembler = self.save_dir.as_ref().map(|dir| {
+            Assembler::new(dir).unwrap_or_else(|err| {
+                print_error(format!("Can't create {}: {}", dir.display(), err));
+                exit(73); // can't create output file
+            })
+        });
+
         // Subscribe to topics
         client.subscribe(self.topics.clone()).unwrap();
 
+        let status = self.receive(&mut client, log.as_mut(), assembler.as_mut(), verbose);
+        if status == EXIT_LIMIT {
+            let _ = client.disconnect();
+        } else {
+            client.terminate();
+        }
+        exit(status);
+    }
+}
+
+impl SubscribeCommand {
+    /// Shows messages until `limit`, `timeout` or an error, giving the exit status
+    pub fn receive(&self, client: &mut Client, mut log: Option<&mut File>, mut assembler: Option<&mut Assembler>, verbose: bool) -> i32 {
+        let mut last_message = Instant::now();
+        let mut shown = 0;
+
         loop {
-            match client.await() {
+            let received = match self.timeout {
+                Some(timeout) => {
+                    let idle = last_message.elapsed();
+                    if idle >= timeout {
+                        print_error(format!("no message within {:?}", timeout));
+                        return EXIT_TIMEOUT;
+                    }
+                    client.poll(timeout - idle)
+                },
+                None => client.await()
+            };
+            match received {
                 Ok(some_message) => {
                     if let Some(ref message) = some_message {
-                        if !self.debug {
+                        if message.qos == QoS::ExactlyOnce {
+                            let _ = client.complete(message.pid.unwrap());
+                        }
+
+                        if !self.shows(message) {
+                            continue;
+                        }
+                        last_message = Instant::now();
+
+                        let received = SystemTime::now();
+                        if let Some(ref mut log) = log {
+                            if let Err(err) = write_log(log, &self.format, message, received) {
+                                print_error(format!("Can't write log file: {}", err));
+                                return 74; // input/output error
+                            }
+                        }
+
+                        if let Some(ref mut assembler) = assembler {
+                            // only whole files count towards the limit
+                            if !save_chunk(assembler, message, verbose) {
+                                continue;
+                            }
+                        } else if !self.debug && self.format != OutputFormat::Pretty {
+                            let stdout = io::stdout();
+                            let mut out = stdout.lock();
+                            let written = self.format.write(&mut out, message, received).and_then(|_| out.flush());
+                            if let Err(err) = written {
+                                // the reader went away, e.g. `| head`
+                                return if err.kind() == io::ErrorKind::BrokenPipe { 0 } else { 74 };
+                            }
+                        } else if !self.debug {
                             let color = match message.qos {
                                 QoS::AtMostOnce => term::color::BRIGHT_CYAN,
                                 QoS::AtLeastOnce => term::color::BRIGHT_MAGENTA,
@@ -146,10 +252,10 @@ impl Command for SubscribeCommand {
                             print_message(&message.topic.path, payload, color);
                         }
 
-                        if message.qos == QoS::ExactlyOnce {
-                            let _ = client.complete(message.pid.unwrap());
+                        shown += 1;
+                        if self.limit == Some(shown) {
+                            return EXIT_LIMIT;
                         }
-
                     }
                 },
                 Err(e) => {
@@ -167,7 +273,7 @@ impl Command for SubscribeCommand {
                             },
                             _ => {
                                 print_error(format!("{:?}", e));
-                                exit(64);
+                                return EXIT_PROTOCOL;
                             }
                         },
                         Error::Storage(ref err) => match *err {
@@ -180,18 +286,53 @@ impl Command for SubscribeCommand {
                             }
                         },
                         Error::Disconnected | Error::ConnectionAbort => {
-                            exit(64);
+                            return EXIT_DISCONNECTED;
                         },
                         e => {
                             print_error(format!("{:?}", e));
-                            client.terminate();
-                            exit(64);
+                            return EXIT_PROTOCOL;
                         }
                     }
                 }
             }
         }
+    }
 
+    /// Whether `message` passes the retain and topic filters
+    pub fn shows(&self, message: &Message) -> bool {
+        (self.retain || !message.retain) &&
+            !self.topic_filters.iter().any(|filter| message.topic.path.contains(filter.as_str()))
+    }
+}
+
+/// Hands `message` to `assembler`, true once it completes a file
+fn save_chunk(assembler: &mut Assembler, message: &Message, verbose: bool) -> bool {
+    match assembler.accept(&message.topic.path, &message.payload) {
+        Ok(Progress::Started(manifest, saved)) => {
+            if verbose {
+                print_message("Receiving", format!("{} {} bytes, {} of {} chunks saved before",
+                                                   manifest.name, manifest.size, saved, manifest.chunks()),
+                              term::color::BRIGHT_GREEN);
+            }
+            false
+        },
+        Ok(Progress::Complete(path)) => {
+            print_message("Saved", path.display().to_string(), term::color::BRIGHT_GREEN);
+            true
+        },
+        Ok(_) => false,
+        Err(err) => {
+            print_error(err);
+            false
+        }
+    }
+}
+
+/// Logs in the output format, pretty output becomes JSON lines
+fn write_log(log: &mut File, format: &OutputFormat, message: &Message, received: SystemTime) -> io::Result<()> {
+    match *format {
+        OutputFormat::Pretty => OutputFormat::Json.write(log, message, received),
+        ref format => format.write(log, message, received)
     }
 }
 
@@ -238,3 +379,90 @@ fn print_message<T: AsRef<str>, M: AsRef<str>>(title: T, message: M, color: u16)
 fn print_error<M: AsRef<str>>(message: M) {
     print_message("Error", message, term::color::BRIGHT_RED);
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use mqtt3::{Message, Packet, PacketIdentifier, Publish, QoS, Suback, SubscribeReturnCodes, TopicPath};
+    use mqttc::{Client, ClientOptions, PubSub};
+    use mqttc::mock::MockBroker;
+    use netopt::NetworkOptions;
+    use super::{SubscribeCommand, EXIT_LIMIT, EXIT_TIMEOUT};
+
+    fn message(topic: &str, retain: bool) -> Message {
+        Message {
+            topic: TopicPath::from_str(topic).unwrap(),
+            qos: QoS::AtMostOnce,
+            retain: retain,
+            pid: None,
+            payload: Arc::new(Vec::new())
+        }
+    }
+
+    fn connect(broker: &MockBroker) -> Client {
+        let mut netopt = NetworkOptions::new();
+        netopt.attach(broker.stream());
+        let mut client = ClientOptions::new().connect("127.0.0.1:1883", netopt).unwrap();
+        client.subscribe("sensors/#").unwrap();
+        client
+    }
+
+    fn suback() -> Packet {
+        Packet::Suback(Box::new(Suback {
+            pid: PacketIdentifier(1),
+            return_codes: vec![SubscribeReturnCodes::Success(QoS::AtMostOnce)]
+        }))
+    }
+
+    fn publish(topic: &str) -> Packet {
+        Packet::Publish(Box::new(Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            retain: false,
+            topic_name: topic.to_owned(),
+            pid: None,
+            payload: Arc::new(b"21.5".to_vec())
+        }))
+    }
+
+    #[test]
+    fn subscribe_filter_test() {
+        let mut command = SubscribeCommand::default();
+        assert!(command.shows(&message("sensors/temp", true)));
+
+        command.retain = false;
+        command.topic_filters = vec!["debug".to_string()];
+        assert!(!command.shows(&message("sensors/temp", true)));
+        assert!(!command.shows(&message("sensors/debug/temp", false)));
+        assert!(command.shows(&message("sensors/temp", false)));
+    }
+
+    #[test]
+    fn subscribe_limit_test() {
+        let mut broker = MockBroker::new();
+        broker.expect_connect().connack(false)
+              .expect_subscribe().reply(suback())
+              .reply(publish("sensors/temp"))
+              .reply(publish("sensors/humidity"));
+        let mut client = connect(&broker);
+
+        let mut command = SubscribeCommand::default();
+        command.limit = Some(2);
+        assert_eq!(command.receive(&mut client, None, None, false), EXIT_LIMIT);
+        broker.assert_done();
+    }
+
+    #[test]
+    fn subscribe_timeout_test() {
+        let mut broker = MockBroker::new();
+        broker.expect_connect().connack(false)
+              .expect_subscribe().reply(suback())
+              .delay(Duration::from_millis(200));
+        let mut client = connect(&broker);
+
+        let mut command = SubscribeCommand::default();
+        command.timeout = Some(Duration::from_millis(50));
+        assert_eq!(command.receive(&mut client, None, None, false), EXIT_TIMEOUT);
+    }
+}
@@ -0,0 +1,418 @@
+//! Chunked file transfer. `publish --chunk-size` sends `<topic>/<id>/manifest`
+//! and then `<topic>/<id>/<index>` for every chunk, `subscribe --save-dir`
+//! puts the file back together.
+//!
+//! The id is the start of the file's SHA-256, so chunks of different files
+//! don't mix. Received chunks are kept in `<dir>/.<id>.part` until the file
+//! is complete, so a receiver that reconnects or restarts picks up where it
+//! left off and a sender only has to send the file again to fill the gaps.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use sha2::{Digest, Sha256};
+use serde_json;
+use super::format::to_hex;
+
+/// Hex digits of the checksum that make up the transfer id
+const ID_LEN: usize = 16;
+
+/// Describes the file a transfer carries, sent as JSON before the chunks
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    // file name without directories
+    pub name: String,
+    pub size: u64,
+    pub chunk_size: u64,
+    // lowercase hex
+    pub sha256: String
+}
+
+impl Manifest {
+    /// Reads `input` to the end for its size and checksum
+    pub fn read<R: Read>(name: &str, chunk_size: u64, mut input: R) -> io::Result<Manifest> {
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        let mut size = 0;
+        loop {
+            match input.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    hasher.update(&buf[..n]);
+                    size += n as u64;
+                },
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err)
+            }
+        }
+        let name = Path::new(name).file_name().map_or("file".to_string(), |name| name.to_string_lossy().into_owned());
+        Ok(Manifest {
+            name: name,
+            size: size,
+            chunk_size: chunk_size,
+            sha256: to_hex(&hasher.finalize(), "")
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Manifest, String> {
+        let raw: RawManifest = serde_json::from_str(text).map_err(|err| err.to_string())?;
+        // the name becomes a path in the save directory
+        if Path::new(&raw.name).file_name().is_none_or(|file| file != raw.name.as_str()) || raw.name.starts_with('.') {
+            return Err(format!("invalid file name {:?}", raw.name));
+        }
+        if raw.sha256.len() != 64 || !raw.sha256.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+            return Err("invalid \"sha256\"".to_string());
+        }
+        let manifest = Manifest {
+            name: raw.name,
+            size: raw.size,
+            chunk_size: raw.chunk_size,
+            sha256: raw.sha256
+        };
+        if manifest.chunk_size == 0 {
+            return Err("chunk_size must not be 0".to_string());
+        }
+        if raw.chunks != manifest.chunks() {
+            return Err("chunks doesn't match size and chunk_size".to_string());
+        }
+        Ok(manifest)
+    }
+
+    pub fn to_json(&self) -> String {
+        let raw = RawManifest {
+            name: self.name.clone(),
+            size: self.size,
+            chunk_size: self.chunk_size,
+            chunks: self.chunks(),
+            sha256: self.sha256.clone()
+        };
+        serde_json::to_string(&raw).expect("a manifest is always valid JSON")
+    }
+
+    pub fn id(&self) -> &str {
+        &self.sha256[..ID_LEN]
+    }
+
+    pub fn chunks(&self) -> u64 {
+        self.size.div_ceil(self.chunk_size)
+    }
+
+    /// Bytes in chunk `index`, only the last one is short
+    pub fn chunk_len(&self, index: u64) -> u64 {
+        self.chunk_size.min(self.size.saturating_sub(index * self.chunk_size))
+    }
+
+    pub fn manifest_topic(&self, topic: &str) -> String {
+        format!("{}/{}/manifest", topic, self.id())
+    }
+
+    pub fn chunk_topic(&self, topic: &str, index: u64) -> String {
+        format!("{}/{}/{}", topic, self.id(), index)
+    }
+}
+
+/// The manifest as sent, `chunks` lets a receiver check it got the right size
+#[derive(Serialize, Deserialize)]
+struct RawManifest {
+    name: String,
+    size: u64,
+    chunk_size: u64,
+    chunks: u64,
+    sha256: String
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Part {
+    Manifest,
+    Chunk(u64)
+}
+
+/// The transfer id and part a topic names, `None` for other topics
+pub fn parse_topic(topic: &str) -> Option<(&str, Part)> {
+    let mut levels = topic.rsplit('/');
+    let last = levels.next()?;
+    let id = levels.next()?;
+    if id.len() != ID_LEN || !id.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+        return None;
+    }
+    let part = if last == "manifest" {
+        Part::Manifest
+    } else if !last.is_empty() && last.bytes().all(|b| b.is_ascii_digit()) {
+        Part::Chunk(last.parse().ok()?)
+    } else {
+        return None;
+    };
+    Some((id, part))
+}
+
+/// What a message did to its transfer
+#[derive(Debug, Clone, PartialEq)]
+pub enum Progress {
+    // not part of a transfer, or a part that is already saved
+    Ignored,
+    // the manifest arrived, with the number of chunks saved before it
+    Started(Manifest, u64),
+    Chunk,
+    // the file is in place and its checksum matches
+    Complete(PathBuf)
+}
+
+#[derive(Debug, Default)]
+struct Transfer {
+    manifest: Option<Manifest>,
+    chunks: BTreeSet<u64>
+}
+
+impl Transfer {
+    /// What an earlier run left in `parts`
+    fn load(parts: &Path) -> io::Result<Transfer> {
+        let mut transfer = Transfer::default();
+        let entries = match fs::read_dir(parts) {
+            Ok(entries) => entries,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(transfer),
+            Err(err) => return Err(err)
+        };
+        for entry in entries {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+            if name == "manifest.json" {
+                let text = fs::read_to_string(parts.join("manifest.json"))?;
+                transfer.manifest = Manifest::parse(&text).ok();
+            } else if let Ok(index) = name.parse() {
+                transfer.chunks.insert(index);
+            }
+        }
+        Ok(transfer)
+    }
+
+    fn is_complete(&self) -> bool {
+        match self.manifest {
+            Some(ref manifest) => self.chunks.len() as u64 >= manifest.chunks() &&
+                (0..manifest.chunks()).all(|index| self.chunks.contains(&index)),
+            None => false
+        }
+    }
+}
+
+/// Saves transfer parts under a directory and assembles the files
+pub struct Assembler {
+    dir: PathBuf,
+    transfers: BTreeMap<String, Transfer>,
+    // finished in this run, so redelivered parts don't start them again
+    complete: BTreeSet<String>
+}
+
+impl Assembler {
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<Assembler> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Assembler {
+            dir: dir.as_ref().to_path_buf(),
+            transfers: BTreeMap::new(),
+            complete: BTreeSet::new()
+        })
+    }
+
+    pub fn accept(&mut self, topic: &str, payload: &[u8]) -> Result<Progress, String> {
+        let (id, part) = match parse_topic(topic) {
+            Some(found) => found,
+            None => return Ok(Progress::Ignored)
+        };
+        if self.complete.contains(id) {
+            return Ok(Progress::Ignored);
+        }
+        let parts = self.dir.join(format!(".{}.part", id));
+        if !self.transfers.contains_key(id) {
+            let transfer = Transfer::load(&parts).map_err(|err| format!("Can't read {}: {}", parts.display(), err))?;
+            self.transfers.insert(id.to_string(), transfer);
+        }
+        let write_error = |err: io::Error| format!("Can't write {}: {}", parts.display(), err);
+
+        let progress = {
+            let transfer = self.transfers.get_mut(id).unwrap();
+            match part {
+                Part::Manifest => {
+                    let text = ::std::str::from_utf8(payload).map_err(|_| format!("{}: manifest is not UTF-8", topic))?;
+                    let manifest = Manifest::parse(text).map_err(|err| format!("{}: {}", topic, err))?;
+                    if manifest.id() != id {
+                        return Err(format!("{}: manifest of another file", topic));
+                    }
+                    match transfer.manifest {
+                        Some(ref known) if *known == manifest => return Ok(Progress::Ignored),
+                        Some(_) => {
+                            // sent again with another chunk size, the saved chunks don't fit
+                            fs::remove_dir_all(&parts).map_err(write_error)?;
+                            *transfer = Transfer::default();
+                        },
+                        None => ()
+                    }
+                    let target = self.dir.join(&manifest.name);
+                    if is_saved(&target, &manifest) {
+                        // sent again, or a retained manifest
+                        self.transfers.remove(id);
+                        self.complete.insert(id.to_string());
+                        let _ = fs::remove_dir_all(&parts);
+                        return Ok(Progress::Complete(target));
+                    }
+                    save(&parts, "manifest.json", manifest.to_json().as_bytes()).map_err(write_error)?;
+                    transfer.manifest = Some(manifest.clone());
+                    Progress::Started(manifest, transfer.chunks.len() as u64)
+                },
+                Part::Chunk(index) => {
+                    if transfer.chunks.contains(&index) {
+                        return Ok(Progress::Ignored);
+                    }
+                    if let Some(ref manifest) = transfer.manifest {
+                        if index >= manifest.chunks() || payload.len() as u64 != manifest.chunk_len(index) {
+                            return Err(format!("{}: chunk doesn't fit the manifest", topic));
+                        }
+                    }
+                    save(&parts, &index.to_string(), payload).map_err(write_error)?;
+                    transfer.chunks.insert(index);
+                    Progress::Chunk
+                }
+            }
+        };
+
+        if self.transfers[id].is_complete() {
+            self.assemble(id, &parts)
+        } else {
+            Ok(progress)
+        }
+    }
+
+    /// Joins the chunks into the file, dropping them when the checksum is wrong
+    fn assemble(&mut self, id: &str, parts: &Path) -> Result<Progress, String> {
+        let manifest = self.transfers.remove(id).and_then(|transfer| transfer.manifest).unwrap();
+        let temp = self.dir.join(format!(".{}.tmp", id));
+        let target = self.dir.join(&manifest.name);
+
+        let written = File::create(&temp).and_then(|mut file| {
+            let mut hasher = Sha256::new();
+            for index in 0..manifest.chunks() {
+                let chunk = fs::read(parts.join(index.to_string()))?;
+                hasher.update(&chunk);
+                file.write_all(&chunk)?;
+            }
+            file.sync_all()?;
+            Ok(to_hex(&hasher.finalize(), ""))
+        });
+        let sha256 = match written {
+            Ok(sha256) => sha256,
+            Err(err) => {
+                let _ = fs::remove_file(&temp);
+                return Err(format!("Can't write {}: {}", temp.display(), err));
+            }
+        };
+        let _ = fs::remove_dir_all(parts);
+        if sha256 != manifest.sha256 {
+            let _ = fs::remove_file(&temp);
+            return Err(format!("{}: checksum mismatch, dropped the chunks", manifest.name));
+        }
+        fs::rename(&temp, &target).map_err(|err| format!("Can't write {}: {}", target.display(), err))?;
+        self.complete.insert(id.to_string());
+        Ok(Progress::Complete(target))
+    }
+}
+
+/// Writes `name` in `dir` through a temporary file, so it is whole or missing
+fn save(dir: &Path, name: &str, data: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let temp = dir.join(format!("{}.tmp", name));
+    fs::write(&temp, data)?;
+    fs::rename(&temp, dir.join(name))
+}
+
+/// Whether `path` already holds the file `manifest` describes
+fn is_saved(path: &Path, manifest: &Manifest) -> bool {
+    match File::open(path) {
+        Ok(file) => Manifest::read(&manifest.name, manifest.chunk_size, file).is_ok_and(|saved| saved == *manifest),
+        Err(_) => false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::env;
+    use std::process;
+    use super::{Manifest, Part, Progress, Assembler, parse_topic};
+
+    fn manifest(data: &[u8], chunk_size: u64) -> Manifest {
+        Manifest::read("/tmp/firmware.bin", chunk_size, data).unwrap()
+    }
+
+    #[test]
+    fn transfer_manifest_test() {
+        let manifest = manifest(b"hello world", 4);
+        assert_eq!(manifest.name, "firmware.bin");
+        assert_eq!(manifest.sha256, "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9");
+        assert_eq!((manifest.chunks(), manifest.chunk_len(0), manifest.chunk_len(2)), (3, 4, 3));
+        assert_eq!(Manifest::parse(&manifest.to_json()).unwrap(), manifest);
+        assert_eq!(manifest.chunk_topic("fw", 2), "fw/b94d27b9934d3e08/2");
+
+        let json = manifest.to_json();
+        for bad in [json.replace("firmware.bin", "../firmware.bin"),
+                    json.replace("firmware.bin", ".hidden"),
+                    json.replace("\"chunks\":3", "\"chunks\":4"),
+                    json.replace("\"chunk_size\":4", "\"chunk_size\":0"),
+                    json.replace("b94d", "B94D")].iter() {
+            assert!(Manifest::parse(bad).is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn transfer_topic_test() {
+        assert_eq!(parse_topic("fw/b94d27b9934d3e08/manifest"), Some(("b94d27b9934d3e08", Part::Manifest)));
+        assert_eq!(parse_topic("b94d27b9934d3e08/12"), Some(("b94d27b9934d3e08", Part::Chunk(12))));
+        assert_eq!(parse_topic("fw/b94d27b9934d3e08/+1"), None);
+        assert_eq!(parse_topic("fw/b94d27b9934d3e0/1"), None);
+        assert_eq!(parse_topic("fw/status"), None);
+    }
+
+    #[test]
+    fn transfer_assemble_test() {
+        let dir = env::temp_dir().join(format!("mqttc-transfer-{}", process::id()));
+        let data = b"hello world";
+        let manifest = manifest(data, 4);
+        let topic = |index| manifest.chunk_topic("fw", index);
+
+        // a chunk before the manifest, then a restart
+        let mut assembler = Assembler::new(&dir).unwrap();
+        assert_eq!(assembler.accept(&topic(2), b"rld"), Ok(Progress::Chunk));
+        assert_eq!(assembler.accept(&topic(2), b"rld"), Ok(Progress::Ignored));
+        assert_eq!(assembler.accept("fw/other", b"x"), Ok(Progress::Ignored));
+
+        let mut assembler = Assembler::new(&dir).unwrap();
+        assert_eq!(assembler.accept(&topic(2), b"rld"), Ok(Progress::Ignored));
+        assert_eq!(assembler.accept(&manifest.manifest_topic("fw"), manifest.to_json().as_bytes()),
+                   Ok(Progress::Started(manifest.clone(), 1)));
+        assert!(assembler.accept(&topic(0), b"hello").is_err());
+        assert_eq!(assembler.accept(&topic(0), b"hell"), Ok(Progress::Chunk));
+        assert_eq!(assembler.accept(&topic(1), b"o wo"), Ok(Progress::Complete(dir.join("firmware.bin"))));
+        assert_eq!(fs::read(dir.join("firmware.bin")).unwrap(), data);
+        assert_eq!(assembler.accept(&topic(1), b"o wo"), Ok(Progress::Ignored));
+
+        // sent again in other chunks
+        fs::remove_file(dir.join("firmware.bin")).unwrap();
+        let mut assembler = Assembler::new(&dir).unwrap();
+        assembler.accept(&manifest.manifest_topic("fw"), manifest.to_json().as_bytes()).unwrap();
+        assembler.accept(&topic(0), b"hell").unwrap();
+        let manifest = self::manifest(data, 8);
+        assert_eq!(assembler.accept(&manifest.manifest_topic("fw"), manifest.to_json().as_bytes()),
+                   Ok(Progress::Started(manifest.clone(), 0)));
+        assembler.accept(&manifest.chunk_topic("fw", 0), b"hello wo").unwrap();
+        assert_eq!(assembler.accept(&manifest.chunk_topic("fw", 1), b"rld"), Ok(Progress::Complete(dir.join("firmware.bin"))));
+        assert_eq!(fs::read(dir.join("firmware.bin")).unwrap(), data);
+
+        // corrupted chunks are dropped
+        let manifest = self::manifest(b"other file", 8);
+        let mut assembler = Assembler::new(&dir).unwrap();
+        assembler.accept(&manifest.manifest_topic("fw"), manifest.to_json().as_bytes()).unwrap();
+        assembler.accept(&manifest.chunk_topic("fw", 0), b"OTHER fi").unwrap();
+        assert!(assembler.accept(&manifest.chunk_topic("fw", 1), b"le").is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
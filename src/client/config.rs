@@ -0,0 +1,270 @@
+//! Connection defaults from `~/.config/mqttc/config.toml`.
+//!
+//! ```toml
+//! # top-level keys apply to every invocation
+//! address = "localhost"
+//!
+//! # picked with --profile prod, overriding the keys above
+//! [profiles.prod]
+//! address = "broker.example.com"
+//! port = 8883
+//! tls = "tlsv1.2"
+//! cafile = "~/certs/ca.pem"
+//! username = "svc"
+//! password_env = "PROD_MQTT_PASSWORD"
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use getopts::Matches;
+use toml::{Table, Value};
+
+/// Config keys and the command line options they stand for
+const KEYS: &[(&str, &str)] = &[
+    ("url", "L"),
+    ("address", "a"),
+    ("port", "p"),
+    ("keep_alive", "k"),
+    ("client_id", "i"),
+    ("username", "u"),
+    ("password", "P"),
+    ("password_file", "password-file"),
+    ("password_env", "password-env"),
+    ("version", "v"),
+    ("tls", "tls"),
+    ("cafile", "cafile"),
+    ("capath", "capath"),
+    ("cert", "cert"),
+    ("key", "key"),
+    ("pkcs12", "pkcs12"),
+    ("pkcs12_password", "pkcs12-password"),
+    ("ciphers", "ciphers"),
+    ("alpn", "alpn"),
+    ("no_verify", "no-verify"),
+    ("proxy", "proxy")
+];
+
+/// Keys holding paths, where a leading `~/` is the home directory
+const PATHS: &[&str] = &["password_file", "cafile", "capath", "cert", "key", "pkcs12"];
+
+/// Option values from the config file, keyed by option name.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    values: BTreeMap<&'static str, String>
+}
+
+impl Profile {
+    /// Reads `path`, or the default config file when there is one, and
+    /// layers the `name` profile over the top-level keys
+    pub fn load(path: Option<String>, name: Option<String>) -> Result<Profile, String> {
+        let explicit = path.or_else(|| env::var("MQTTC_CONFIG").ok());
+        let path = match explicit.clone().map(PathBuf::from).or_else(default_path) {
+            Some(path) => path,
+            None => return Profile::empty(name)
+        };
+        let mut text = String::new();
+        match File::open(&path).and_then(|mut file| file.read_to_string(&mut text)) {
+            Ok(_) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound && explicit.is_none() => return Profile::empty(name),
+            Err(err) => return Err(format!("Can't read {}: {}", path.display(), err))
+        }
+        Profile::parse(&text, name.as_deref())
+            .map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub fn parse(text: &str, name: Option<&str>) -> Result<Profile, String> {
+        let mut table: Table = text.parse().map_err(|err: toml::de::Error| err.message().to_string())?;
+        let profiles = match table.remove("profiles") {
+            Some(Value::Table(profiles)) => profiles,
+            Some(_) => return Err("profiles must be a table".to_string()),
+            None => Table::new()
+        };
+
+        let mut profile = Profile::default();
+        profile.merge(&table)?;
+        if let Some(name) = name {
+            match profiles.get(name) {
+                Some(Value::Table(overrides)) => profile.merge(overrides)?,
+                _ => return Err(format!("no profile named {:?}", name))
+            }
+        }
+        Ok(profile)
+    }
+
+    fn empty(name: Option<String>) -> Result<Profile, String> {
+        match name {
+            Some(name) => Err(format!("no config file to read profile {:?} from", name)),
+            None => Ok(Profile::default())
+        }
+    }
+
+    fn merge(&mut self, table: &Table) -> Result<(), String> {
+        for (key, value) in table {
+            let option = match KEYS.iter().find(|&&(name, _)| name == key) {
+                Some(&(_, option)) => option,
+                None => return Err(format!("unknown key {:?}", key))
+            };
+            let mut value = match *value {
+                Value::String(ref s) => s.clone(),
+                Value::Integer(n) => n.to_string(),
+                Value::Float(n) => n.to_string(),
+                Value::Boolean(b) => b.to_string(),
+                _ => return Err(format!("{} must be a string, number or boolean", key))
+            };
+            if PATHS.contains(&key.as_str()) && value.starts_with("~/") {
+                if let Some(home) = env::var_os("HOME") {
+                    value = PathBuf::from(home).join(&value[2..]).to_string_lossy().into_owned();
+                }
+            }
+            self.values.insert(option, value);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, option: &str) -> Option<&String> {
+        self.values.get(option)
+    }
+}
+
+/// `$XDG_CONFIG_HOME/mqttc/config.toml`, falling back to `~/.config`
+fn default_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("mqttc").join("config.toml"))
+}
+
+/// Parsed command line with the profile filling in options that weren't given.
+pub struct Args {
+    matches: Matches,
+    profile: Profile,
+    pub free: Vec<String>
+}
+
+impl Args {
+    pub fn new(matches: Matches, profile: Profile) -> Args {
+        Args {
+            free: matches.free.clone(),
+            matches: matches,
+            profile: profile
+        }
+    }
+
+    pub fn opt_present(&self, name: &str) -> bool {
+        self.matches.opt_present(name) || self.profile(name).is_some_and(|value| value != "false")
+    }
+
+    pub fn opt_str(&self, name: &str) -> Option<String> {
+        self.matches.opt_str(name).or_else(|| self.profile(name).cloned())
+    }
+
+    /// The profile value, except a profile URL when `-a` or `-p` are given,
+    /// as it would take their place
+    fn profile(&self, name: &str) -> Option<&String> {
+        if name == "L" && self.matches.opts_present(&["a".to_string(), "p".to_string()]) {
+            return None;
+        }
+        self.profile.get(name)
+    }
+
+    pub fn opt_strs(&self, name: &str) -> Vec<String> {
+        self.matches.opt_strs(name)
+    }
+
+    /// `-P`, `--password-file` or `--password-env`, from the command line
+    /// before the profile
+    pub fn password(&self) -> Result<Option<String>, String> {
+        let source = |cli: bool, name: &str| if cli {
+            self.matches.opt_str(name)
+        } else {
+            self.profile.get(name).cloned()
+        };
+        for &cli in [true, false].iter() {
+            if let Some(password) = source(cli, "P") {
+                return Ok(Some(password));
+            }
+            if let Some(path) = source(cli, "password-file") {
+                let mut password = String::new();
+                File::open(&path).and_then(|mut file| file.read_to_string(&mut password))
+                    .map_err(|err| format!("Can't read password file {}: {}", path, err))?;
+                // the first line, so a trailing newline isn't part of it
+                return Ok(Some(password.lines().next().unwrap_or("").to_string()));
+            }
+            if let Some(var) = source(cli, "password-env") {
+                return env::var(&var).map(Some).map_err(|_| format!("environment variable {} is not set", var));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use getopts::Options;
+    use super::{Args, Profile};
+
+    const CONFIG: &str = r#"
+        address = "localhost"
+        keep_alive = 10
+
+        [profiles.prod]
+        address = "broker.example.com"
+        port = 8883
+        tls = true
+        password_env = "MQTTC_TEST_PASSWORD"
+    "#;
+
+    fn args(cli: &[&str], profile: Profile) -> Args {
+        let mut opts = Options::new();
+        opts.optopt("L", "", "", "");
+        opts.optopt("a", "", "", "");
+        opts.optopt("p", "", "", "");
+        opts.optopt("k", "", "", "");
+        opts.optopt("P", "", "", "");
+        opts.optopt("", "password-file", "", "");
+        opts.optopt("", "password-env", "", "");
+        opts.optopt("", "tls", "", "");
+        Args::new(opts.parse(cli).unwrap(), profile)
+    }
+
+    #[test]
+    fn profile_test() {
+        let base = Profile::parse(CONFIG, None).unwrap();
+        assert_eq!(base.get("a").unwrap(), "localhost");
+        assert_eq!(base.get("k").unwrap(), "10");
+        assert!(base.get("p").is_none());
+
+        let prod = Profile::parse(CONFIG, Some("prod")).unwrap();
+        assert_eq!(prod.get("a").unwrap(), "broker.example.com");
+        assert_eq!(prod.get("p").unwrap(), "8883");
+        assert_eq!(prod.get("k").unwrap(), "10");
+
+        assert!(Profile::parse(CONFIG, Some("staging")).is_err());
+        assert!(Profile::parse("adress = \"typo\"", None).is_err());
+    }
+
+    #[test]
+    fn profile_override_test() {
+        let prod = Profile::parse(CONFIG, Some("prod")).unwrap();
+        let args = args(&["-p", "1883"], prod.clone());
+        assert_eq!(args.opt_str("a").unwrap(), "broker.example.com");
+        assert_eq!(args.opt_str("p").unwrap(), "1883");
+        assert!(args.opt_present("tls"));
+
+        ::std::env::set_var("MQTTC_TEST_PASSWORD", "from-env");
+        assert_eq!(args.password().unwrap().unwrap(), "from-env");
+        assert_eq!(self::args(&["-P", "secret"], prod.clone()).password().unwrap().unwrap(), "secret");
+        assert!(self::args(&["--password-env", "MQTTC_TEST_UNSET"], prod).password().is_err());
+    }
+
+    #[test]
+    fn profile_url_test() {
+        let profile = Profile::parse("url = \"mqtts://broker.example.com\"", None).unwrap();
+        assert_eq!(args(&[], profile.clone()).opt_str("L").unwrap(), "mqtts://broker.example.com");
+        assert!(args(&["-a", "other"], profile.clone()).opt_str("L").is_none());
+        assert!(args(&["-p", "1884"], profile.clone()).opt_str("L").is_none());
+        assert_eq!(args(&["-L", "mqtt://other"], profile).opt_str("L").unwrap(), "mqtt://other");
+    }
+}
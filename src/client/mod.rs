@@ -1,6 +1,7 @@
 mod logger;
 mod command;
 mod cli;
+mod config;
 
 pub use self::cli::CLI;
 pub use self::command::Command;
@@ -1,9 +1,18 @@
 #[macro_use] extern crate log;
 extern crate term;
 extern crate getopts;
+#[cfg(feature = "ssl")]
 extern crate openssl;
 extern crate mqtt3;
 extern crate netopt;
 extern crate mqttc;
+extern crate toml;
+extern crate rustyline;
+extern crate crossterm;
+#[macro_use]
+extern crate serde;
+extern crate serde_json;
+extern crate base64;
+extern crate sha2;
 
 pub mod client;